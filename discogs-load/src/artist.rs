@@ -1,10 +1,11 @@
 use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
+use std::{collections::HashMap, error::Error, mem, str};
 
-use crate::db::{write_artists, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::{Batch, DbOpt, SqlSerialization, Writer};
+use crate::parser::{lossy_text, Parser, ParserOpt};
+use crate::skip_log::SkipLog;
 
 #[derive(Clone, Debug)]
 pub struct Artist {
@@ -17,6 +18,8 @@ pub struct Artist {
     pub urls: Vec<String>,
     pub aliases: Vec<String>,
     pub members: Vec<String>,
+    pub groups: Vec<String>,
+    pub profile_raw: Option<String>,
 }
 
 impl SqlSerialization for Artist {
@@ -31,6 +34,8 @@ impl SqlSerialization for Artist {
             &self.urls,
             &self.aliases,
             &self.members,
+            &self.groups,
+            &self.profile_raw,
         ];
         row
     }
@@ -48,6 +53,8 @@ impl Artist {
             urls: Vec::new(),
             aliases: Vec::new(),
             members: Vec::new(),
+            groups: Vec::new(),
+            profile_raw: None,
         }
     }
 }
@@ -61,6 +68,7 @@ enum ParserState {
     Profile,
     DataQuality,
     NameVariations,
+    NameVariation,
     Url,
     Urls,
     Alias,
@@ -68,6 +76,7 @@ enum ParserState {
     Member,
     Members,
     Groups,
+    Group,
     Images,
 }
 
@@ -77,39 +86,34 @@ pub struct ArtistsParser<'a> {
     current_artist: Artist,
     pb: ProgressBar,
     db_opts: &'a DbOpt,
+    writer: Writer<'a>,
+    skip_log: SkipLog,
+    parser_opts: &'a ParserOpt,
 }
 
 impl<'a> ArtistsParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+    pub fn new(db_opts: &'a DbOpt, parser_opts: &'a ParserOpt) -> Self {
         ArtistsParser {
             state: ParserState::Artist,
             artists: HashMap::new(),
             current_artist: Artist::new(),
-            pb: ProgressBar::new(7993954),
+            pb: parser_opts.progress_bar(),
             db_opts,
+            writer: Writer::new(db_opts),
+            skip_log: SkipLog::new(parser_opts.skip_log.clone()),
+            parser_opts,
         }
     }
 }
 
-impl<'a> Parser<'a> for ArtistsParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        ArtistsParser {
-            state: ParserState::Artist,
-            artists: HashMap::new(),
-            current_artist: Artist::new(),
-            pb: ProgressBar::new(7993954),
-            db_opts,
-        }
-    }
+impl<'a> Parser for ArtistsParser<'a> {
     fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
         self.state = match self.state {
             ParserState::Artist => {
                 match ev {
                     Event::Start(e) if e.local_name() == b"artist" => {
-                        self.current_artist.name_variations = Vec::new();
-                        self.current_artist.urls = Vec::new();
-                        self.current_artist.aliases = Vec::new();
-                        self.current_artist.members = Vec::new();
+                        // nothing carries over from the previous artist, e.g. a missing <profile>
+                        self.current_artist = Artist::new();
                         ParserState::Artist
                     }
 
@@ -133,21 +137,28 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
                     Event::End(e) if e.local_name() == b"artist" => {
                         if self.current_artist.id == 170355 {println!("\n\nInserted DP\n\n")}
-                        self.artists
-                            .entry(self.current_artist.id)
-                            .or_insert(self.current_artist.clone());
+                        let skipped = self
+                            .parser_opts
+                            .skip_reason(&self.current_artist, &self.current_artist.data_quality)?;
+                        match skipped {
+                            None => {
+                                self.artists
+                                    .entry(self.current_artist.id)
+                                    .or_insert(self.current_artist.clone());
+                            }
+                            Some(reason) => {
+                                self.skip_log.record("artist", self.current_artist.id, &reason)?
+                            }
+                        }
                         if self.artists.len() >= self.db_opts.batch_size {
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_artists(self.db_opts, &self.artists)?;
-                            self.artists = HashMap::new();
+                            self.writer.write(Batch::Artists(mem::take(&mut self.artists)))?;
                         }
                         self.pb.inc(1);
                         ParserState::Artist
                     }
 
                     Event::End(e) if e.local_name() == b"artists" => {
-                        // write to db remainder of artists
-                        write_artists(self.db_opts, &self.artists)?;
+                        self.finish()?;
                         ParserState::Artist
                     }
 
@@ -168,7 +179,8 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Name => match ev {
                 Event::Text(e) => {
-                    self.current_artist.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.name =
+                        lossy_text(&e.unescaped()?, "artist", self.current_artist.id);
                     ParserState::Name
                 }
 
@@ -179,7 +191,8 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::RealName => match ev {
                 Event::Text(e) => {
-                    self.current_artist.real_name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.real_name =
+                        lossy_text(&e.unescaped()?, "artist", self.current_artist.id);
                     ParserState::RealName
                 }
 
@@ -190,7 +203,10 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Profile => match ev {
                 Event::Text(e) => {
-                    self.current_artist.profile = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let profile = lossy_text(&e.unescaped()?, "artist", self.current_artist.id);
+                    let (profile, raw) = self.parser_opts.clean_markup(profile);
+                    self.current_artist.profile = profile;
+                    self.current_artist.profile_raw = raw;
                     ParserState::Profile
                 }
 
@@ -202,7 +218,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
             ParserState::DataQuality => match ev {
                 Event::Text(e) => {
                     self.current_artist.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                        lossy_text(&e.unescaped()?, "artist", self.current_artist.id);
                     ParserState::DataQuality
                 }
 
@@ -221,9 +237,11 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Url => match ev {
                 Event::Text(e) => {
-                    self.current_artist
-                        .urls
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_artist.urls.push(lossy_text(
+                        &e.unescaped()?,
+                        "artist",
+                        self.current_artist.id,
+                    ));
                     ParserState::Urls
                 }
 
@@ -242,9 +260,11 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Alias => match ev {
                 Event::Text(e) => {
-                    self.current_artist
-                        .members
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_artist.aliases.push(lossy_text(
+                        &e.unescaped()?,
+                        "artist",
+                        self.current_artist.id,
+                    ));
                     ParserState::Aliases
                 }
 
@@ -261,20 +281,51 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
                 _ => ParserState::Members,
             },
 
+            // the bands the artist is in, <name id="..."> per group
             ParserState::Groups => match ev {
-                // Event::Start(e) if e.local_name() == b"member" => ParserState::Member,
+                Event::Start(e) if e.local_name() == b"name" => ParserState::Group,
 
                 Event::End(e) if e.local_name() == b"groups" => ParserState::Artist,
                 _ => ParserState::Groups,
             },
 
+            ParserState::Group => match ev {
+                Event::Text(e) => {
+                    self.current_artist.groups.push(lossy_text(
+                        &e.unescaped()?,
+                        "artist",
+                        self.current_artist.id,
+                    ));
+                    ParserState::Group
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserState::Groups,
+
+                _ => ParserState::Group,
+            },
+
             ParserState::NameVariations => match ev {
-                // Event::Start(e) if e.local_name() == b"member" => ParserState::Member,
+                Event::Start(e) if e.local_name() == b"name" => ParserState::NameVariation,
 
                 Event::End(e) if e.local_name() == b"namevariations" => ParserState::Artist,
                 _ => ParserState::NameVariations,
             },
 
+            ParserState::NameVariation => match ev {
+                Event::Text(e) => {
+                    self.current_artist.name_variations.push(lossy_text(
+                        &e.unescaped()?,
+                        "artist",
+                        self.current_artist.id,
+                    ));
+                    ParserState::NameVariation
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserState::NameVariations,
+
+                _ => ParserState::NameVariation,
+            },
+
             ParserState::Images => match ev {
                 // Event::Start(e) if e.local_name() == b"member" => ParserState::Member,
 
@@ -284,9 +335,11 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Member => match ev {
                 Event::Text(e) => {
-                    self.current_artist
-                        .members
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_artist.members.push(lossy_text(
+                        &e.unescaped()?,
+                        "artist",
+                        self.current_artist.id,
+                    ));
                     ParserState::Members
                 }
 
@@ -297,4 +350,35 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
         Ok(())
     }
+
+    fn context(&self) -> String {
+        format!("artist {}, field {:?}", self.current_artist.id, self.state)
+    }
+
+    fn current_record(&self) -> (&'static str, i32) {
+        ("artist", self.current_artist.id)
+    }
+
+    fn discard_record(&mut self) {
+        self.current_artist = Artist::new();
+        self.state = ParserState::Artist;
+    }
+
+    fn skip_log(&mut self) -> &mut SkipLog {
+        &mut self.skip_log
+    }
+
+    fn records(&self) -> u64 {
+        self.pb.position()
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // write to db remainder of artists
+        if !self.artists.is_empty() {
+            self.writer.write(Batch::Artists(mem::take(&mut self.artists)))?;
+        }
+        self.writer.finish()?;
+        self.skip_log.log_counts();
+        Ok(())
+    }
 }
@@ -0,0 +1,131 @@
+use log::warn;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::artist::Artist;
+use crate::label::Label;
+use crate::master::Master;
+use crate::release::Release;
+
+/// What a record failing a `--check` does.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckAction {
+    Warn,
+    Skip,
+    Abort,
+}
+
+impl FromStr for CheckAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(CheckAction::Warn),
+            "skip" => Ok(CheckAction::Skip),
+            "abort" => Ok(CheckAction::Abort),
+            _ => Err(format!("expected warn, skip or abort, got {}", s)),
+        }
+    }
+}
+
+pub type Check<T> = (&'static str, fn(&T) -> bool);
+
+/// A record type with checks that `--check <entity>.<name>` can enable.
+pub trait Checked: 'static {
+    const ENTITY: &'static str;
+    const CHECKS: &'static [Check<Self>];
+    fn id(&self) -> i32;
+}
+
+impl Checked for Release {
+    const ENTITY: &'static str = "release";
+    const CHECKS: &'static [Check<Self>] = &[
+        ("title-non-empty", |r| !r.title.trim().is_empty()),
+        ("id-positive", |r| r.id > 0),
+        ("released-year", |r| {
+            r.released.is_empty()
+                || matches!(r.released.get(..4).map(str::parse::<i32>), Some(Ok(1860..=2100)))
+        }),
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Checked for Master {
+    const ENTITY: &'static str = "master";
+    const CHECKS: &'static [Check<Self>] = &[
+        ("title-non-empty", |m| !m.title.trim().is_empty()),
+        ("id-positive", |m| m.id > 0),
+        ("year", |m| m.year == 0 || (1860..=2100).contains(&m.year)),
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Checked for Artist {
+    const ENTITY: &'static str = "artist";
+    const CHECKS: &'static [Check<Self>] = &[
+        ("name-non-empty", |a| !a.name.trim().is_empty()),
+        ("id-positive", |a| a.id > 0),
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Checked for Label {
+    const ENTITY: &'static str = "label";
+    const CHECKS: &'static [Check<Self>] = &[
+        ("name-non-empty", |l| !l.name.trim().is_empty()),
+        ("id-positive", |l| l.id > 0),
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+/// Parse a `--check` name, making sure it exists.
+pub fn parse_check(s: &str) -> Result<String, String> {
+    if known::<Release>(s) || known::<Master>(s) || known::<Artist>(s) || known::<Label>(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("unknown check {}, e.g. release.title-non-empty", s))
+    }
+}
+
+fn known<T: Checked>(check: &str) -> bool {
+    T::CHECKS.iter().any(|(name, _)| check == format!("{}.{}", T::ENTITY, name))
+}
+
+/// Run the enabled checks on a finished record. Returns the check it fails under
+/// `--check-action skip`, or an error when one fails under `--check-action abort`.
+pub fn run<T: Checked>(
+    record: &T,
+    enabled: &[String],
+    action: CheckAction,
+) -> Result<Option<String>, String> {
+    for (name, passes) in T::CHECKS {
+        let check = format!("{}.{}", T::ENTITY, name);
+        if !enabled.contains(&check) || passes(record) {
+            continue;
+        }
+        match action {
+            CheckAction::Warn => warn!("{} {} fails {}", T::ENTITY, record.id(), check),
+            CheckAction::Skip => {
+                warn!("Skipping {} {}, it fails {}", T::ENTITY, record.id(), check);
+                return Ok(Some(check));
+            }
+            CheckAction::Abort => {
+                return Err(format!("{} {} fails {}", T::ENTITY, record.id(), check))
+            }
+        }
+    }
+    Ok(None)
+}
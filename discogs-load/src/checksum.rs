@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::{fs, fs::File, io, path::Path};
+
+/// Check a dump against its line in a Discogs CHECKSUM.txt, `<sha256> <file name>` per dump.
+pub fn verify(checksums: &Path, dump: &Path) -> Result<()> {
+    let name = dump
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("invalid dump file name {}", dump.display()))?;
+    let contents = fs::read_to_string(checksums)?;
+    let expected = contents
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(hash, _)| hash.to_lowercase())
+        .ok_or_else(|| anyhow!("no checksum for {} in {}", name, checksums.display()))?;
+
+    info!("Verifying the checksum of {}", name);
+    let mut hasher = Sha256::new();
+    // streamed, the dumps are several GB
+    io::copy(&mut File::open(dump)?, &mut hasher)?;
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != expected {
+        return Err(anyhow!(
+            "checksum mismatch for {}: expected {}, got {}, the download may be truncated",
+            name,
+            expected,
+            actual
+        ));
+    }
+    Ok(())
+}
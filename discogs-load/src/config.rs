@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use structopt::clap::ArgMatches;
+
+use crate::db::{Backend, InsertMode, SslMode};
+use crate::checks::{parse_check, CheckAction};
+use crate::parser::parse_quality;
+use crate::Opt;
+
+/// Option defaults read from a `--config` file. Keys are the long flag names,
+/// e.g. `db-host = "localhost"` or `batch-size = 5000`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pretty_errors: Option<bool>,
+    backend: Option<Backend>,
+    out_file: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    max_errors: Option<usize>,
+    verify_checksum: Option<PathBuf>,
+    create_indexes: Option<bool>,
+    index_connections: Option<usize>,
+    check_main_releases: Option<bool>,
+    report_orphans: Option<PathBuf>,
+    post_check: Option<bool>,
+    post_check_tolerance: Option<u32>,
+    batch_size: Option<usize>,
+    max_batch_bytes: Option<usize>,
+    copy_flush_rows: Option<usize>,
+    insert_mode: Option<InsertMode>,
+    db_url: Option<String>,
+    db_host: Option<String>,
+    db_port: Option<u16>,
+    db_sslmode: Option<SslMode>,
+    db_user: Option<String>,
+    db_password: Option<String>,
+    db_name: Option<String>,
+    schema: Option<String>,
+    #[serde(rename = "async")]
+    async_writes: Option<bool>,
+    async_writers: Option<usize>,
+    unlogged: Option<bool>,
+    parallel_copies: Option<bool>,
+    writer_thread: Option<bool>,
+    max_retries: Option<u32>,
+    on_conflict: Option<bool>,
+    dry_run: Option<bool>,
+    checkpoint: Option<PathBuf>,
+    resume: Option<bool>,
+    source_label: Option<String>,
+    min_track_count: Option<usize>,
+    denormalize: Option<bool>,
+    no_labels: Option<bool>,
+    no_videos: Option<bool>,
+    no_tracks: Option<bool>,
+    no_formats: Option<bool>,
+    skip_tables: Option<Vec<String>>,
+    min_quality: Option<String>,
+    strip_markup: Option<bool>,
+    summarize_facets: Option<bool>,
+    keep_rejected: Option<bool>,
+    format_hints: Option<bool>,
+    progress_hz: Option<u64>,
+    byte_progress: Option<bool>,
+    quiet: Option<bool>,
+    limit: Option<u64>,
+    expected_count: Option<u64>,
+    primary_label: Option<bool>,
+    skip_log: Option<PathBuf>,
+    split_titles: Option<bool>,
+    main_releases_only: Option<bool>,
+    filter_country: Option<Vec<String>>,
+    filter_genre: Option<Vec<String>>,
+    filter_style: Option<Vec<String>>,
+    check: Option<Vec<String>>,
+    check_action: Option<CheckAction>,
+    log_counts: Option<u64>,
+}
+
+impl Config {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| anyhow!("invalid config {}: {}", path.display(), e))
+    }
+
+    /// Set every option that is in the config but wasn't given on the command line.
+    pub fn apply(self, opt: &mut Opt, matches: &ArgMatches) -> Result<()> {
+        let unset = |flag: &str| matches.occurrences_of(flag) == 0;
+        macro_rules! apply {
+            ($($key:ident => $field:expr),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$key {
+                        if unset(&stringify!($key).replace('_', "-")) {
+                            $field = value;
+                        }
+                    }
+                )*
+            };
+        }
+        apply! {
+            pretty_errors => opt.pretty_errors,
+            max_errors => opt.max_errors,
+            backend => opt.dbopts.backend,
+            create_indexes => opt.dbopts.create_indexes,
+            index_connections => opt.dbopts.index_connections,
+            check_main_releases => opt.dbopts.check_main_releases,
+            post_check => opt.dbopts.post_check,
+            post_check_tolerance => opt.dbopts.post_check_tolerance,
+            batch_size => opt.dbopts.batch_size,
+            insert_mode => opt.dbopts.insert_mode,
+            db_host => opt.dbopts.db_host,
+            db_port => opt.dbopts.db_port,
+            db_sslmode => opt.dbopts.db_sslmode,
+            db_user => opt.dbopts.db_user,
+            db_password => opt.dbopts.db_password,
+            db_name => opt.dbopts.db_name,
+            async_writes => opt.dbopts.async_writes,
+            async_writers => opt.dbopts.async_writers,
+            unlogged => opt.dbopts.unlogged,
+            parallel_copies => opt.dbopts.parallel_copies,
+            writer_thread => opt.dbopts.writer_thread,
+            max_retries => opt.dbopts.max_retries,
+            on_conflict => opt.dbopts.on_conflict,
+            dry_run => opt.dbopts.dry_run,
+            resume => opt.dbopts.resume,
+            min_track_count => opt.parseropts.min_track_count,
+            denormalize => opt.dbopts.denormalize,
+            no_labels => opt.parseropts.no_labels,
+            no_videos => opt.parseropts.no_videos,
+            no_tracks => opt.parseropts.no_tracks,
+            no_formats => opt.parseropts.no_formats,
+            skip_tables => opt.parseropts.skip_tables,
+            strip_markup => opt.parseropts.strip_markup,
+            summarize_facets => opt.parseropts.summarize_facets,
+            keep_rejected => opt.parseropts.keep_rejected,
+            format_hints => opt.parseropts.format_hints,
+            progress_hz => opt.parseropts.progress_hz,
+            byte_progress => opt.parseropts.byte_progress,
+            quiet => opt.parseropts.quiet,
+            primary_label => opt.parseropts.primary_label,
+            split_titles => opt.parseropts.split_titles,
+            main_releases_only => opt.parseropts.main_releases_only,
+            filter_country => opt.parseropts.filter_country,
+            filter_genre => opt.parseropts.filter_genre,
+            filter_style => opt.parseropts.filter_style,
+            check_action => opt.parseropts.check_action,
+        }
+        if let Some(source_label) = self.source_label {
+            if unset("source-label") {
+                opt.dbopts.source_label = Some(source_label);
+            }
+        }
+        if let Some(schema) = self.schema {
+            if unset("schema") {
+                opt.dbopts.schema = Some(schema);
+            }
+        }
+        if let Some(url) = self.db_url {
+            if unset("db-url") {
+                opt.dbopts.db_url = Some(url);
+            }
+        }
+        if let Some(bytes) = self.max_batch_bytes {
+            if unset("max-batch-bytes") {
+                opt.dbopts.max_batch_bytes = Some(bytes);
+            }
+        }
+        if let Some(rows) = self.copy_flush_rows {
+            if unset("copy-flush-rows") {
+                opt.dbopts.copy_flush_rows = Some(rows);
+            }
+        }
+        if let Some(limit) = self.limit {
+            if unset("limit") {
+                opt.parseropts.limit = Some(limit);
+            }
+        }
+        if let Some(count) = self.expected_count {
+            if unset("expected-count") {
+                opt.parseropts.expected_count = Some(count);
+            }
+        }
+        if let Some(secs) = self.log_counts {
+            if unset("log-counts") {
+                opt.dbopts.log_counts = Some(secs);
+            }
+        }
+        if let Some(path) = self.out_file {
+            if unset("out-file") {
+                opt.dbopts.out_file = Some(path);
+            }
+        }
+        if let Some(path) = self.out_dir {
+            if unset("out-dir") {
+                opt.dbopts.out_dir = Some(path);
+            }
+        }
+        if let Some(path) = self.report_orphans {
+            if unset("report-orphans") {
+                opt.dbopts.report_orphans = Some(path);
+            }
+        }
+        if let Some(path) = self.checkpoint {
+            if unset("checkpoint") {
+                opt.dbopts.checkpoint = Some(path);
+            }
+        }
+        if let Some(path) = self.verify_checksum {
+            if unset("verify-checksum") {
+                opt.verify_checksum = Some(path);
+            }
+        }
+        if let Some(path) = self.skip_log {
+            if unset("skip-log") {
+                opt.parseropts.skip_log = Some(path);
+            }
+        }
+        if let Some(checks) = self.check {
+            if unset("checks") {
+                opt.parseropts.checks = checks
+                    .iter()
+                    .map(|c| parse_check(c))
+                    .collect::<Result<_, String>>()
+                    .map_err(|e| anyhow!(e))?;
+            }
+        }
+        if let Some(quality) = self.min_quality {
+            if unset("min-quality") {
+                opt.parseropts.min_quality = Some(parse_quality(&quality).map_err(|e| anyhow!(e))?);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use log::error;
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+use tokio::{runtime::Runtime, sync::Semaphore, task::JoinHandle};
+use tokio_postgres::{binary_copy::BinaryCopyInWriter, Client, NoTls};
+
+use crate::db::{
+    Batch, DbOpt, InsertCommand, SqlSerialization, Table, ARTIST, FORMAT, LABEL, MASTER,
+    MASTER_ARTIST, RELEASE, RELEASE_ARTIST, RELEASE_COMPANY, RELEASE_EXTRAARTIST,
+    RELEASE_IDENTIFIER, RELEASE_IMAGE, RELEASE_LABEL, RELEASE_REJECTED, RELEASE_VIDEO, TRACK,
+    TRACK_ARTIST,
+};
+
+/// Writes each batch as a tokio task, with at most `--async-writers` in flight.
+pub struct AsyncWriter {
+    runtime: Runtime,
+    permits: Arc<Semaphore>,
+    tasks: Vec<JoinHandle<Result<()>>>,
+    // connections not in use by a task, at most one per writer
+    clients: Arc<Mutex<Vec<Client>>>,
+    db_opts: Arc<DbOpt>,
+}
+
+impl AsyncWriter {
+    pub fn new(db_opts: &DbOpt) -> Self {
+        AsyncWriter {
+            runtime: Runtime::new().expect("failed to start the tokio runtime"),
+            permits: Arc::new(Semaphore::new(db_opts.async_writers.max(1))),
+            tasks: Vec::new(),
+            clients: Arc::new(Mutex::new(Vec::new())),
+            db_opts: Arc::new(db_opts.clone()),
+        }
+    }
+
+    /// Start writing a batch, waiting first if all writers are busy.
+    pub fn write(&mut self, batch: Batch) -> Result<()> {
+        let permit = self.runtime.block_on(self.permits.clone().acquire_owned())?;
+        // fail on the first batch that didn't make it, not at the end of the dump
+        let (finished, running) = mem::take(&mut self.tasks)
+            .into_iter()
+            .partition::<Vec<_>, _>(|task| task.is_finished());
+        self.tasks = running;
+        for task in finished {
+            self.runtime.block_on(task)??;
+        }
+        let db_opts = self.db_opts.clone();
+        let clients = self.clients.clone();
+        self.tasks.push(self.runtime.spawn(async move {
+            let pooled = clients.lock().unwrap().pop().filter(|client| !client.is_closed());
+            let client = match pooled {
+                Some(client) => client,
+                None => connect(&db_opts).await?,
+            };
+            let written = write_batch(&client, &db_opts, &batch).await;
+            if written.is_ok() {
+                // a connection that failed mid-batch isn't reused
+                clients.lock().unwrap().push(client);
+            }
+            drop(permit);
+            written
+        }));
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        for task in self.tasks.drain(..) {
+            self.runtime.block_on(task)??;
+        }
+        Ok(())
+    }
+}
+
+async fn write_batch(client: &Client, db_opts: &DbOpt, batch: &Batch) -> Result<()> {
+    // one transaction per batch, so its tables commit or roll back together
+    client.batch_execute("BEGIN").await?;
+    match write_tables(client, db_opts, batch).await {
+        Ok(()) => {
+            client.batch_execute("COMMIT").await?;
+            Ok(())
+        }
+        Err(e) => {
+            client.batch_execute("ROLLBACK").await.ok();
+            Err(e)
+        }
+    }
+}
+
+async fn write_tables(client: &Client, db_opts: &DbOpt, batch: &Batch) -> Result<()> {
+    match batch {
+        Batch::Releases(batch) => {
+            write_rows(client, batch.releases.values(), &RELEASE, db_opts).await?;
+            write_rows(client, batch.rejected.values(), &RELEASE_REJECTED, db_opts).await?;
+            write_rows(client, batch.labels.values(), &RELEASE_LABEL, db_opts).await?;
+            write_rows(client, batch.videos.values(), &RELEASE_VIDEO, db_opts).await?;
+            write_rows(client, batch.tracks.values(), &TRACK, db_opts).await?;
+            write_rows(client, batch.formats.values(), &FORMAT, db_opts).await?;
+            write_rows(client, batch.artists.values(), &RELEASE_ARTIST, db_opts).await?;
+            write_rows(client, batch.extra_artists.values(), &RELEASE_EXTRAARTIST, db_opts).await?;
+            write_rows(client, batch.identifiers.values(), &RELEASE_IDENTIFIER, db_opts).await?;
+            write_rows(client, batch.companies.values(), &RELEASE_COMPANY, db_opts).await?;
+            write_rows(client, batch.images.values(), &RELEASE_IMAGE, db_opts).await?;
+            write_rows(client, batch.track_artists.values(), &TRACK_ARTIST, db_opts).await?;
+        }
+        Batch::Labels(labels) => write_rows(client, labels.values(), &LABEL, db_opts).await?,
+        Batch::Artists(artists) => write_rows(client, artists.values(), &ARTIST, db_opts).await?,
+        Batch::Masters { masters, artists } => {
+            write_rows(client, masters.values(), &MASTER, db_opts).await?;
+            write_rows(client, artists.values(), &MASTER_ARTIST, db_opts).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn connect(db_opts: &DbOpt) -> Result<Client> {
+    let params = db_opts.connection_string();
+    let client = match db_opts.tls_connector()? {
+        Some(tls) => {
+            let (client, connection) = tokio_postgres::connect(&params, tls).await?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("Database connection error: {}", e);
+                }
+            });
+            client
+        }
+        None => {
+            let (client, connection) = tokio_postgres::connect(&params, NoTls).await?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("Database connection error: {}", e);
+                }
+            });
+            client
+        }
+    };
+    if let Some(search_path) = db_opts.search_path() {
+        client.batch_execute(&search_path).await?;
+    }
+    Ok(client)
+}
+
+async fn write_rows<'a, I, T>(client: &Client, data: I, table: &Table, db_opts: &DbOpt) -> Result<()>
+where
+    I: Iterator<Item = &'a T>,
+    T: SqlSerialization + 'a,
+{
+    // skip the COPY entirely for tables with nothing to write
+    let mut data = data.peekable();
+    if data.peek().is_none() {
+        return Ok(());
+    }
+    let insert_cmd = InsertCommand::new(table, db_opts)?;
+    let sink = client.copy_in(insert_cmd.copy_stm.as_str()).await?;
+    let writer = BinaryCopyInWriter::new(sink, &insert_cmd.col_types);
+    tokio::pin!(writer);
+    for row in data {
+        writer.as_mut().write(&insert_cmd.row(row)).await?;
+    }
+    writer.as_mut().finish().await?;
+    Ok(())
+}
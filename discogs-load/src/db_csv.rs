@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use postgres::types::{FromSql, IsNull, ToSql, Type};
+use std::collections::{hash_map::Entry, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::db::{
+    Batch, SqlSerialization, Table, ARTIST, FORMAT, LABEL, MASTER, MASTER_ARTIST, RELEASE,
+    RELEASE_ARTIST, RELEASE_COMPANY, RELEASE_EXTRAARTIST, RELEASE_IDENTIFIER, RELEASE_IMAGE,
+    RELEASE_LABEL, RELEASE_REJECTED, RELEASE_VIDEO, TRACK, TRACK_ARTIST,
+};
+
+/// Writes batches to a CSV file per table in a directory, with `--backend csv`.
+pub struct CsvWriter {
+    dir: PathBuf,
+    field_map: Vec<(String, String)>,
+    source_label: Option<String>,
+    denormalize: bool,
+    // files already (re)created by this writer
+    files: HashMap<&'static str, BufWriter<File>>,
+}
+
+impl CsvWriter {
+    pub fn new(
+        dir: PathBuf,
+        field_map: &[(String, String)],
+        source_label: Option<String>,
+        denormalize: bool,
+    ) -> Self {
+        CsvWriter {
+            dir,
+            field_map: field_map.to_vec(),
+            source_label,
+            denormalize,
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn write(&mut self, batch: Batch) -> Result<()> {
+        match &batch {
+            Batch::Releases(batch) => {
+                self.write_rows(batch.releases.values(), &RELEASE)?;
+                self.write_rows(batch.rejected.values(), &RELEASE_REJECTED)?;
+                self.write_rows(batch.labels.values(), &RELEASE_LABEL)?;
+                self.write_rows(batch.videos.values(), &RELEASE_VIDEO)?;
+                self.write_rows(batch.tracks.values(), &TRACK)?;
+                self.write_rows(batch.formats.values(), &FORMAT)?;
+                self.write_rows(batch.artists.values(), &RELEASE_ARTIST)?;
+                self.write_rows(batch.extra_artists.values(), &RELEASE_EXTRAARTIST)?;
+                self.write_rows(batch.identifiers.values(), &RELEASE_IDENTIFIER)?;
+                self.write_rows(batch.companies.values(), &RELEASE_COMPANY)?;
+                self.write_rows(batch.images.values(), &RELEASE_IMAGE)?;
+                self.write_rows(batch.track_artists.values(), &TRACK_ARTIST)?;
+            }
+            Batch::Labels(labels) => self.write_rows(labels.values(), &LABEL)?,
+            Batch::Artists(artists) => self.write_rows(artists.values(), &ARTIST)?,
+            Batch::Masters { masters, artists } => {
+                self.write_rows(masters.values(), &MASTER)?;
+                self.write_rows(artists.values(), &MASTER_ARTIST)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_rows<'b, I, T>(&mut self, data: I, table: &Table) -> Result<()>
+    where
+        I: Iterator<Item = &'b T>,
+        T: SqlSerialization + 'b,
+    {
+        let file = match self.files.entry(table.name) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                fs::create_dir_all(&self.dir)?;
+                let path = self.dir.join(format!("{}.csv", table.name));
+                let mut file = BufWriter::new(File::create(path)?);
+                let mut header = table.column_names(&self.field_map, self.denormalize);
+                if self.source_label.is_some() {
+                    header.push("source");
+                }
+                writeln!(file, "{}", header.join(","))?;
+                entry.insert(file)
+            }
+        };
+        let columns = table.row_columns(self.denormalize);
+        for row in data {
+            let mut fields = row
+                .to_sql()
+                .into_iter()
+                .zip(&columns)
+                .map(|(value, (_, col_type))| to_csv(value, col_type))
+                .collect::<Result<Vec<String>>>()?;
+            if let Some(source_label) = &self.source_label {
+                fields.push(quote(source_label));
+            }
+            writeln!(file, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A CSV field through the value's Postgres binary encoding, like the DuckDB writer.
+/// NULL is an empty field, arrays are JSON.
+fn to_csv(value: &(dyn ToSql + Sync), col_type: &Type) -> Result<String> {
+    let mut buf = BytesMut::new();
+    if let IsNull::Yes = value.to_sql_checked(col_type, &mut buf).map_err(|e| anyhow!(e))? {
+        return Ok(String::new());
+    }
+    let field = if *col_type == Type::INT4 {
+        i32::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?.to_string()
+    } else if *col_type == Type::BOOL {
+        bool::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?.to_string()
+    } else if *col_type == Type::TEXT_ARRAY {
+        let values = Vec::<String>::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?;
+        quote(&serde_json::to_string(&values)?)
+    } else {
+        quote(&String::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?)
+    };
+    Ok(field)
+}
+
+/// Always quoted, so an empty string stays apart from NULL.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use duckdb::{params_from_iter, types::Value, Connection};
+use postgres::types::{FromSql, IsNull, ToSql, Type};
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::db::{
+    Batch, SqlSerialization, Table, ARTIST, FORMAT, LABEL, MASTER, MASTER_ARTIST, RELEASE,
+    RELEASE_ARTIST, RELEASE_COMPANY, RELEASE_EXTRAARTIST, RELEASE_IDENTIFIER, RELEASE_IMAGE,
+    RELEASE_LABEL, RELEASE_REJECTED, RELEASE_VIDEO, TRACK, TRACK_ARTIST,
+};
+
+/// Writes batches into a DuckDB file, with `--backend duckdb`.
+pub struct DuckDbWriter {
+    path: PathBuf,
+    field_map: Vec<(String, String)>,
+    source_label: Option<String>,
+    denormalize: bool,
+    connection: Option<Connection>,
+    // tables already (re)created by this writer
+    created: HashSet<&'static str>,
+}
+
+impl DuckDbWriter {
+    pub fn new(
+        path: PathBuf,
+        field_map: &[(String, String)],
+        source_label: Option<String>,
+        denormalize: bool,
+    ) -> Self {
+        DuckDbWriter {
+            path,
+            field_map: field_map.to_vec(),
+            source_label,
+            denormalize,
+            connection: None,
+            created: HashSet::new(),
+        }
+    }
+
+    pub fn write(&mut self, batch: Batch) -> Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(Connection::open(&self.path)?);
+        }
+        let tx = self.connection.as_mut().unwrap().transaction()?;
+        let mut tables = TableWriter {
+            tx: &tx,
+            field_map: &self.field_map,
+            source_label: &self.source_label,
+            denormalize: self.denormalize,
+            created: &mut self.created,
+        };
+        match &batch {
+            Batch::Releases(batch) => {
+                tables.write_rows(batch.releases.values(), &RELEASE)?;
+                tables.write_rows(batch.rejected.values(), &RELEASE_REJECTED)?;
+                tables.write_rows(batch.labels.values(), &RELEASE_LABEL)?;
+                tables.write_rows(batch.videos.values(), &RELEASE_VIDEO)?;
+                tables.write_rows(batch.tracks.values(), &TRACK)?;
+                tables.write_rows(batch.formats.values(), &FORMAT)?;
+                tables.write_rows(batch.artists.values(), &RELEASE_ARTIST)?;
+                tables.write_rows(batch.extra_artists.values(), &RELEASE_EXTRAARTIST)?;
+                tables.write_rows(batch.identifiers.values(), &RELEASE_IDENTIFIER)?;
+                tables.write_rows(batch.companies.values(), &RELEASE_COMPANY)?;
+                tables.write_rows(batch.images.values(), &RELEASE_IMAGE)?;
+                tables.write_rows(batch.track_artists.values(), &TRACK_ARTIST)?;
+            }
+            Batch::Labels(labels) => tables.write_rows(labels.values(), &LABEL)?,
+            Batch::Artists(artists) => tables.write_rows(artists.values(), &ARTIST)?,
+            Batch::Masters { masters, artists } => {
+                tables.write_rows(masters.values(), &MASTER)?;
+                tables.write_rows(artists.values(), &MASTER_ARTIST)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+struct TableWriter<'a> {
+    tx: &'a Connection,
+    field_map: &'a [(String, String)],
+    source_label: &'a Option<String>,
+    denormalize: bool,
+    created: &'a mut HashSet<&'static str>,
+}
+
+impl<'a> TableWriter<'a> {
+    fn write_rows<'b, I, T>(&mut self, data: I, table: &Table) -> Result<()>
+    where
+        I: Iterator<Item = &'b T>,
+        T: SqlSerialization + 'b,
+    {
+        let mut columns = table.column_names(self.field_map, self.denormalize);
+        let row_columns = table.row_columns(self.denormalize);
+        if self.created.insert(table.name) {
+            let mut definitions: Vec<String> = columns
+                .iter()
+                .zip(&row_columns)
+                .map(|(name, (_, col_type))| format!("{} {}", name, duckdb_type(col_type)))
+                .collect();
+            if self.source_label.is_some() {
+                definitions.push(String::from("source VARCHAR"));
+            }
+            self.tx.execute_batch(&format!(
+                "CREATE OR REPLACE TABLE {} ({})",
+                table.name,
+                definitions.join(", ")
+            ))?;
+        }
+
+        // arrays are bound as JSON text and cast to LISTs
+        let mut placeholders: Vec<&str> = row_columns
+            .iter()
+            .map(|(_, col_type)| {
+                if *col_type == Type::TEXT_ARRAY {
+                    "CAST(CAST(? AS JSON) AS VARCHAR[])"
+                } else {
+                    "?"
+                }
+            })
+            .collect();
+        if self.source_label.is_some() {
+            columns.push("source");
+            placeholders.push("?");
+        }
+        let mut stmt = self.tx.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table.name,
+            columns.join(", "),
+            placeholders.join(", ")
+        ))?;
+        for row in data {
+            let mut values = row
+                .to_sql()
+                .into_iter()
+                .zip(&row_columns)
+                .map(|(value, (_, col_type))| to_duckdb(value, col_type))
+                .collect::<Result<Vec<Value>>>()?;
+            if let Some(source_label) = self.source_label {
+                values.push(Value::Text(source_label.clone()));
+            }
+            stmt.execute(params_from_iter(values))?;
+        }
+        Ok(())
+    }
+}
+
+fn duckdb_type(col_type: &Type) -> &'static str {
+    if *col_type == Type::INT4 {
+        "INTEGER"
+    } else if *col_type == Type::BOOL {
+        "BOOLEAN"
+    } else if *col_type == Type::TEXT_ARRAY {
+        "VARCHAR[]"
+    } else {
+        "VARCHAR"
+    }
+}
+
+/// Convert a value through its Postgres binary encoding, so rows keep a single SqlSerialization.
+fn to_duckdb(value: &(dyn ToSql + Sync), col_type: &Type) -> Result<Value> {
+    let mut buf = BytesMut::new();
+    if let IsNull::Yes = value.to_sql_checked(col_type, &mut buf).map_err(|e| anyhow!(e))? {
+        return Ok(Value::Null);
+    }
+    let value = if *col_type == Type::INT4 {
+        Value::Int(i32::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?)
+    } else if *col_type == Type::BOOL {
+        Value::Boolean(bool::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?)
+    } else if *col_type == Type::TEXT_ARRAY {
+        let values = Vec::<String>::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?;
+        Value::Text(serde_json::to_string(&values)?)
+    } else {
+        Value::Text(String::from_sql(col_type, &buf).map_err(|e| anyhow!(e))?)
+    };
+    Ok(value)
+}
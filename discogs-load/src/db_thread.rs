@@ -0,0 +1,57 @@
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::db::{write_batch, Batch, Db, DbOpt};
+
+/// Hands batches over a channel to a thread holding the connection, with `--writer-thread`,
+/// so parsing carries on while a batch is copied.
+pub struct ThreadWriter {
+    sender: Option<SyncSender<Batch>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl ThreadWriter {
+    pub fn new(db_opts: &DbOpt) -> Self {
+        // one batch waiting besides the one being written, so memory stays bounded
+        let (sender, receiver) = mpsc::sync_channel::<Batch>(1);
+        let db_opts = db_opts.clone();
+        let handle = thread::spawn(move || {
+            let mut db = None;
+            for batch in receiver {
+                if db.is_none() {
+                    db = Some(Db::connect(&db_opts)?);
+                }
+                write_batch(db.as_mut().unwrap(), &db_opts, batch)?;
+            }
+            Ok(())
+        });
+        ThreadWriter {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Queue a batch, waiting first if the thread is still busy with the last one.
+    pub fn write(&mut self, batch: Batch) -> Result<()> {
+        if let Some(sender) = &self.sender {
+            if sender.send(batch).is_ok() {
+                return Ok(());
+            }
+        }
+        // the thread stopped on an error, report that one
+        self.finish()?;
+        Err(anyhow!("the writer thread has stopped"))
+    }
+
+    /// Close the channel and wait for the queued batches to be written.
+    pub fn finish(&mut self) -> Result<()> {
+        self.sender.take();
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow!("the writer thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
+use std::{collections::HashMap, error::Error, mem, str};
 
-use crate::db::{write_labels, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::{Batch, DbOpt, SqlSerialization, Writer};
+use crate::parser::{lossy_text, Parser, ParserOpt};
+use crate::skip_log::SkipLog;
 
 #[derive(Clone, Debug)]
 pub struct Label {
@@ -16,6 +17,7 @@ pub struct Label {
     pub sublabels: Vec<String>,
     pub urls: Vec<String>,
     pub data_quality: String,
+    pub profile_raw: Option<String>,
 }
 
 impl SqlSerialization for Label {
@@ -29,6 +31,7 @@ impl SqlSerialization for Label {
             &self.sublabels,
             &self.urls,
             &self.data_quality,
+            &self.profile_raw,
         ];
         row
     }
@@ -45,6 +48,7 @@ impl Label {
             sublabels: Vec::new(),
             urls: Vec::new(),
             data_quality: String::new(),
+            profile_raw: None,
         }
     }
 }
@@ -70,37 +74,34 @@ pub struct LabelsParser<'a> {
     current_label: Label,
     pb: ProgressBar,
     db_opts: &'a DbOpt,
+    writer: Writer<'a>,
+    skip_log: SkipLog,
+    parser_opts: &'a ParserOpt,
 }
 
 impl<'a> LabelsParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+    pub fn new(db_opts: &'a DbOpt, parser_opts: &'a ParserOpt) -> Self {
         LabelsParser {
             state: ParserState::Label,
             labels: HashMap::new(),
             current_label: Label::new(),
-            pb: ProgressBar::new(1821993),
+            pb: parser_opts.progress_bar(),
             db_opts,
+            writer: Writer::new(db_opts),
+            skip_log: SkipLog::new(parser_opts.skip_log.clone()),
+            parser_opts,
         }
     }
 }
 
-impl<'a> Parser<'a> for LabelsParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        LabelsParser {
-            state: ParserState::Label,
-            labels: HashMap::new(),
-            current_label: Label::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
-        }
-    }
+impl<'a> Parser for LabelsParser<'a> {
     fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
         self.state = match self.state {
             ParserState::Label => {
                 match ev {
                     Event::Start(e) if e.local_name() == b"label" => {
-                        self.current_label.sublabels = Vec::new();
-                        self.current_label.urls = Vec::new();
+                        // nothing carries over from the previous label, e.g. a missing <profile>
+                        self.current_label = Label::new();
                         ParserState::Label
                     }
 
@@ -117,21 +118,28 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
                     },
 
                     Event::End(e) if e.local_name() == b"label" => {
-                        self.labels
-                            .entry(self.current_label.id)
-                            .or_insert(self.current_label.clone());
+                        let skipped = self
+                            .parser_opts
+                            .skip_reason(&self.current_label, &self.current_label.data_quality)?;
+                        match skipped {
+                            None => {
+                                self.labels
+                                    .entry(self.current_label.id)
+                                    .or_insert(self.current_label.clone());
+                            }
+                            Some(reason) => {
+                                self.skip_log.record("label", self.current_label.id, &reason)?
+                            }
+                        }
                         if self.labels.len() >= self.db_opts.batch_size {
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_labels(self.db_opts, &self.labels)?;
-                            self.labels = HashMap::new();
+                            self.writer.write(Batch::Labels(mem::take(&mut self.labels)))?;
                         }
                         self.pb.inc(1);
                         ParserState::Label
                     }
 
                     Event::End(e) if e.local_name() == b"labels" => {
-                        // write to db remainder of labels
-                        write_labels(self.db_opts, &self.labels)?;
+                        self.finish()?;
                         ParserState::Label
                     }
 
@@ -152,7 +160,8 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Name => match ev {
                 Event::Text(e) => {
-                    self.current_label.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.name =
+                        lossy_text(&e.unescaped()?, "label", self.current_label.id);
                     ParserState::Name
                 }
 
@@ -163,7 +172,8 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Contactinfo => match ev {
                 Event::Text(e) => {
-                    self.current_label.contactinfo = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.contactinfo =
+                        lossy_text(&e.unescaped()?, "label", self.current_label.id);
                     ParserState::Contactinfo
                 }
 
@@ -174,7 +184,10 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Profile => match ev {
                 Event::Text(e) => {
-                    self.current_label.profile = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let profile = lossy_text(&e.unescaped()?, "label", self.current_label.id);
+                    let (profile, raw) = self.parser_opts.clean_markup(profile);
+                    self.current_label.profile = profile;
+                    self.current_label.profile_raw = raw;
                     ParserState::Profile
                 }
 
@@ -185,7 +198,8 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::ParentLabel => match ev {
                 Event::Text(e) => {
-                    self.current_label.parent_label = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.parent_label =
+                        lossy_text(&e.unescaped()?, "label", self.current_label.id);
                     ParserState::ParentLabel
                 }
 
@@ -204,9 +218,11 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Sublabel => match ev {
                 Event::Text(e) => {
-                    self.current_label
-                        .sublabels
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_label.sublabels.push(lossy_text(
+                        &e.unescaped()?,
+                        "label",
+                        self.current_label.id,
+                    ));
                     ParserState::Sublabel
                 }
 
@@ -225,9 +241,11 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Url => match ev {
                 Event::Text(e) => {
-                    self.current_label
-                        .urls
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_label.urls.push(lossy_text(
+                        &e.unescaped()?,
+                        "label",
+                        self.current_label.id,
+                    ));
                     ParserState::Url
                 }
 
@@ -238,7 +256,8 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::DataQuality => match ev {
                 Event::Text(e) => {
-                    self.current_label.data_quality = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.data_quality =
+                        lossy_text(&e.unescaped()?, "label", self.current_label.id);
                     ParserState::DataQuality
                 }
 
@@ -250,4 +269,35 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
         Ok(())
     }
+
+    fn context(&self) -> String {
+        format!("label {}, field {:?}", self.current_label.id, self.state)
+    }
+
+    fn current_record(&self) -> (&'static str, i32) {
+        ("label", self.current_label.id)
+    }
+
+    fn discard_record(&mut self) {
+        self.current_label = Label::new();
+        self.state = ParserState::Label;
+    }
+
+    fn skip_log(&mut self) -> &mut SkipLog {
+        &mut self.skip_log
+    }
+
+    fn records(&self) -> u64 {
+        self.pb.position()
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // write to db remainder of labels
+        if !self.labels.is_empty() {
+            self.writer.write(Batch::Labels(mem::take(&mut self.labels)))?;
+        }
+        self.writer.finish()?;
+        self.skip_log.log_counts();
+        Ok(())
+    }
 }
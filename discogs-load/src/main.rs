@@ -1,82 +1,348 @@
-use anyhow::Result;
+use ansi_term::Colour::{Blue, Red};
+use anyhow::{anyhow, Result};
 use flate2::read::GzDecoder;
-use log::info;
+use log::{error, info, warn};
 use quick_xml::{events::Event, Reader};
-use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal, Read},
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
 
 mod artist;
+mod checks;
+mod checksum;
+mod config;
 mod db;
+mod db_csv;
+mod db_thread;
+#[cfg(feature = "async")]
+mod db_async;
+#[cfg(feature = "duckdb")]
+mod db_duckdb;
 mod label;
 mod master;
 mod parser;
 mod release;
+mod schema;
+mod skip_log;
 
 const BUF_SIZE: usize = 4096; // 4kb at once
+const SNIPPET_LEN: usize = 80;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "discogs-load")]
 struct Opt {
-    /// Path to one or more discogs monthly data dump files, still compressed
+    /// Path or http(s) URL of one or more discogs monthly data dump files, gzipped or plain XML,
+    /// or - to read one from stdin
     #[structopt(name = "FILE(S)", parse(from_os_str))]
     files: Vec<PathBuf>,
 
+    /// TOML file with defaults for any option not given on the command line
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Print errors in color with the record and value that caused them
+    #[structopt(long = "pretty-errors")]
+    pretty_errors: bool,
+
+    /// Print an Avro schema for each table and exit
+    #[structopt(long = "emit-avro-schema")]
+    emit_avro_schema: bool,
+
+    /// Print a JSON Schema for each table and exit
+    #[structopt(long = "emit-json-schema")]
+    emit_json_schema: bool,
+
+    /// Discogs CHECKSUM.txt to verify each dump against before loading it
+    #[structopt(long = "verify-checksum", parse(from_os_str))]
+    verify_checksum: Option<PathBuf>,
+
+    /// Number of records with parse errors to log and skip before aborting
+    #[structopt(long = "max-errors", default_value = "0")]
+    max_errors: usize,
+
     // DB related arguments
     #[structopt(flatten)]
     dbopts: db::DbOpt,
+
+    // Parser related arguments
+    #[structopt(flatten)]
+    parseropts: parser::ParserOpt,
 }
 
 fn main() -> Result<()> {
     let log_env = env_logger::Env::default().default_filter_or("info");
     env_logger::Builder::from_env(log_env).init();
 
-    let opt = Opt::from_args();
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    if let Some(path) = opt.config.clone() {
+        config::Config::read(&path)?.apply(&mut opt, &matches)?;
+    }
+
+    if opt.dbopts.async_writes && !cfg!(feature = "async") {
+        return Err(anyhow!("--async needs discogs-load built with --features async"));
+    }
+    if opt.dbopts.resume && opt.dbopts.checkpoint.is_none() {
+        return Err(anyhow!("--resume needs a --checkpoint file"));
+    }
+    if opt.dbopts.checkpoint.is_some() && opt.dbopts.async_writes {
+        // background batches can finish out of order
+        return Err(anyhow!("--checkpoint can't be combined with --async"));
+    }
+    if opt.dbopts.checkpoint.is_some() && opt.dbopts.writer_thread {
+        // the checkpoint would be written before the thread has copied the batch
+        return Err(anyhow!("--checkpoint can't be combined with --writer-thread"));
+    }
+    if opt.dbopts.checkpoint.is_some() && opt.dbopts.parallel_copies {
+        // each table commits on its own, a crash can leave a batch half written
+        return Err(anyhow!("--checkpoint can't be combined with --parallel-copies"));
+    }
+    if opt.dbopts.writer_thread && opt.dbopts.async_writes {
+        return Err(anyhow!("--writer-thread can't be combined with --async"));
+    }
+    if opt.dbopts.on_conflict && opt.dbopts.async_writes {
+        return Err(anyhow!("--on-conflict can't be combined with --async"));
+    }
+    if opt.dbopts.parallel_copies && opt.dbopts.async_writes {
+        return Err(anyhow!("--parallel-copies can't be combined with --async"));
+    }
+    if (opt.dbopts.copy_flush_rows.is_some() || opt.dbopts.insert_mode == db::InsertMode::Insert)
+        && opt.dbopts.async_writes
+    {
+        return Err(anyhow!(
+            "--copy-flush-rows and --insert-mode insert can't be combined with --async"
+        ));
+    }
+    if matches.occurrences_of("max-retries") > 0 && opt.dbopts.async_writes {
+        // the async tasks don't retry a failed batch
+        return Err(anyhow!("--max-retries can't be combined with --async"));
+    }
+    if let Some(table) = opt
+        .parseropts
+        .skip_tables
+        .iter()
+        .find(|table| !parser::SKIPPABLE_TABLES.contains(&table.as_str()))
+    {
+        return Err(anyhow!(
+            "--skip-tables: {} isn't one of {}",
+            table,
+            parser::SKIPPABLE_TABLES.join(", ")
+        ));
+    }
+    if opt.verify_checksum.is_some()
+        && opt.files.iter().any(|file| is_url(file) || is_stdin(file))
+    {
+        // the whole download would be hashed first and then streamed again
+        return Err(anyhow!("--verify-checksum needs local dump files, not URLs or stdin"));
+    }
+    if opt.files.iter().filter(|file| is_stdin(file)).count() > 1 {
+        return Err(anyhow!("- can only be given once, stdin is read to the end"));
+    }
+    if opt.dbopts.backend == db::Backend::DuckDb {
+        if !cfg!(feature = "duckdb") {
+            return Err(anyhow!("--backend duckdb needs discogs-load built with --features duckdb"));
+        }
+        if opt.dbopts.out_file.is_none() {
+            return Err(anyhow!("--backend duckdb needs an --out-file"));
+        }
+    }
+    if opt.dbopts.backend == db::Backend::Csv && opt.dbopts.out_dir.is_none() {
+        return Err(anyhow!("--backend csv needs an --out-dir"));
+    }
+    if opt.dbopts.backend != db::Backend::Postgres
+        && (opt.dbopts.async_writes
+            || opt.dbopts.create_indexes
+            || opt.dbopts.check_main_releases
+            || opt.dbopts.report_orphans.is_some()
+            || opt.dbopts.post_check
+            || opt.dbopts.on_conflict
+            || opt.dbopts.schema.is_some()
+            || opt.dbopts.parallel_copies
+            || opt.dbopts.writer_thread
+            || opt.dbopts.copy_flush_rows.is_some()
+            || opt.dbopts.insert_mode == db::InsertMode::Insert)
+    {
+        return Err(anyhow!(
+            "--async, --create-indexes, --check-main-releases, --report-orphans, --post-check, \
+             --on-conflict, --schema, --parallel-copies, --writer-thread, --copy-flush-rows and \
+             --insert-mode insert need --backend postgres"
+        ));
+    }
+
+    if opt.emit_avro_schema || opt.emit_json_schema {
+        let schemas = if opt.emit_avro_schema {
+            schema::avro_schemas(&opt.dbopts.field_map, opt.dbopts.denormalize)
+        } else {
+            schema::json_schemas(&opt.dbopts.field_map, opt.dbopts.denormalize)
+        };
+        for schema in schemas {
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+        }
+        return Ok(());
+    }
 
     if let Err(e) = read_files(&opt) {
-        println!("{:?}", e);
+        report_error(&*e, opt.pretty_errors);
         std::process::exit(1);
     }
     Ok(())
 }
 
+/// An error raised by a parser, with where in the dump it happened.
+#[derive(Debug)]
+struct ParseError {
+    file: String,
+    position: usize,
+    context: String,
+    snippet: String,
+    source: Box<dyn Error>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {} at byte {} ({}), value: {:?}",
+            self.source, self.file, self.position, self.context, self.snippet
+        )
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// Print an error to stderr, colored with --pretty-errors when it is a terminal.
+fn report_error(e: &(dyn Error + 'static), pretty: bool) {
+    if !pretty || !std::io::stderr().is_terminal() {
+        eprintln!("error: {}", e);
+        return;
+    }
+    match e.downcast_ref::<ParseError>() {
+        Some(e) => {
+            let bar = Blue.bold().paint("   |");
+            eprintln!("{} {}", Red.bold().paint("error:"), e.source);
+            eprintln!("{} {} at byte {}", Blue.bold().paint("  -->"), e.file, e.position);
+            eprintln!("{} {}", bar, e.context);
+            eprintln!("{} {} {:?}", bar, Blue.bold().paint("value:"), e.snippet);
+        }
+        None => eprintln!("{} {}", Red.bold().paint("error:"), e),
+    }
+}
+
+fn is_url(file: &Path) -> bool {
+    file.to_str().is_some_and(|f| f.starts_with("http://") || f.starts_with("https://"))
+}
+
+fn is_stdin(file: &Path) -> bool {
+    file == Path::new("-")
+}
+
+/// A dump to read, with its length in bytes if known.
+type Input = (Box<dyn Read>, Option<u64>);
+
+/// Open a dump file, or stream it when given as a URL or `-` for stdin.
+fn open_input(file: &Path) -> Result<Input, Box<dyn Error>> {
+    if is_stdin(file) {
+        return Ok((Box::new(io::stdin()), None));
+    }
+    if is_url(file) {
+        let response = ureq::get(&file.to_string_lossy()).call()?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok());
+        return Ok((Box::new(response.into_reader()), len));
+    }
+    let dump = File::open(file)?;
+    let len = dump.metadata()?.len();
+    Ok((Box::new(dump), Some(len)))
+}
+
+/// Decompress a dump on the fly if it has a .gz extension or starts like a gzip file.
+fn open_dump(dump: Box<dyn Read>, file: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut dump = BufReader::new(dump);
+    let gzipped = file.extension().is_some_and(|ext| ext == "gz")
+        || dump.fill_buf()?.starts_with(&GZIP_MAGIC);
+    if gzipped {
+        Ok(Box::new(BufReader::new(GzDecoder::new(dump))))
+    } else {
+        Ok(Box::new(dump))
+    }
+}
+
 fn read_files(opt: &Opt) -> Result<(), Box<dyn Error>> {
+    let mut errors = 0;
+    if let Some(path) = &opt.parseropts.skip_log {
+        // start each run with an empty log, the parsers append to it
+        File::create(path)?;
+    }
+    // the entities loaded, for --post-check
+    let mut entities = Vec::new();
     for file in &opt.files {
-        let gzfile = File::open(file.to_str().unwrap())?;
-        let xmlfile = GzDecoder::new(gzfile);
-        let xmlfile = BufReader::new(xmlfile);
+        if let Some(checksums) = &opt.verify_checksum {
+            // before init drops the tables of a previous load
+            checksum::verify(checksums, file)?;
+        }
+        // read once, so stdin works too, the parsers don't need the root element
+        let (dump, len) = open_input(file)?;
+        let mut parser_opts = opt.parseropts.clone();
+        let dump: Box<dyn Read> = match len {
+            Some(len) if parser_opts.byte_progress => {
+                let pb = parser_opts.byte_progress_bar(len);
+                Box::new(pb.wrap_read(dump))
+            }
+            _ => {
+                // stdin or a URL without a Content-Length, count records instead
+                parser_opts.byte_progress = false;
+                dump
+            }
+        };
+        let xmlfile = open_dump(dump, file)?;
         let mut xmlfile = Reader::from_reader(xmlfile);
         let mut buf = Vec::with_capacity(BUF_SIZE);
 
         // Parse fileinput on type (label/release/artist)
-        let mut parser: Box<dyn parser::Parser> = loop {
+        let mut parser: Box<dyn parser::Parser + '_> = loop {
             if let Event::Start(ref e) = xmlfile.read_event(&mut buf)? {
                 match e.name() {
                     b"labels" => {
-                        db::init(&opt.dbopts, "sql/tables/label.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &label::LabelsParser::new(&opt.dbopts),
+                        db::init(&opt.dbopts, &opt.dbopts.schema_file("label"))?;
+                        entities.push("label");
+                        break Box::new(label::LabelsParser::new(
                             &opt.dbopts,
+                            &parser_opts,
                         ));
                     }
                     b"releases" => {
-                        db::init(&opt.dbopts, "sql/tables/release.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &release::ReleasesParser::new(&opt.dbopts),
+                        db::init(&opt.dbopts, &opt.dbopts.schema_file("release"))?;
+                        entities.push("release");
+                        break Box::new(release::ReleasesParser::new(
                             &opt.dbopts,
-                        ));
+                            &parser_opts,
+                        )?);
                     }
                     b"artists" => {
-                        db::init(&opt.dbopts, "sql/tables/artist.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &artist::ArtistsParser::new(&opt.dbopts),
+                        db::init(&opt.dbopts, &opt.dbopts.schema_file("artist"))?;
+                        entities.push("artist");
+                        break Box::new(artist::ArtistsParser::new(
                             &opt.dbopts,
+                            &parser_opts,
                         ));
                     }
                     b"masters" => {
-                        db::init(&opt.dbopts, "sql/tables/master.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &master::MastersParser::new(&opt.dbopts),
+                        db::init(&opt.dbopts, &opt.dbopts.schema_file("master"))?;
+                        entities.push("master");
+                        break Box::new(master::MastersParser::new(
                             &opt.dbopts,
+                            &parser_opts,
                         ));
                     }
                     _ => (),
@@ -87,24 +353,132 @@ fn read_files(opt: &Opt) -> Result<(), Box<dyn Error>> {
         };
 
         // Parse and insert file
-        let gzfile = File::open(file.to_str().unwrap())?;
-        let xmlfile = GzDecoder::new(gzfile);
-        let xmlfile = BufReader::new(xmlfile);
-        let mut xmlfile = Reader::from_reader(xmlfile);
-        let mut buf = Vec::with_capacity(BUF_SIZE);
+        buf.clear();
         info!("Parsing and inserting: {:?}", file.file_name().unwrap());
+        // elements open, counting the root, to skip the rest of a record after an error
+        let mut depth = 1;
+        let mut skipping = false;
         loop {
             match xmlfile.read_event(&mut buf)? {
-                Event::Eof => break,
-                ev => parser.process(ev)?,
+                Event::Eof => {
+                    // flush the tail batch even if the root closing tag was never seen
+                    parser.finish()?;
+                    break;
+                }
+                ev => {
+                    match ev {
+                        Event::Start(_) => depth += 1,
+                        Event::End(_) => depth -= 1,
+                        _ => {}
+                    }
+                    if skipping {
+                        // until the closing tag of the record that failed
+                        skipping = depth > 1;
+                        buf.clear();
+                        continue;
+                    }
+                    if let Err(e) = parser.process(ev) {
+                        // a failed batch isn't a bad record, skipping it would lose the whole batch
+                        if e.is::<db::WriteError>() {
+                            return Err(e);
+                        }
+                        let e = ParseError {
+                            file: file.display().to_string(),
+                            position: xmlfile.buffer_position(),
+                            context: parser.context(),
+                            snippet: String::from_utf8_lossy(&buf)
+                                .chars()
+                                .take(SNIPPET_LEN)
+                                .collect(),
+                            source: e,
+                        };
+                        errors += 1;
+                        let (entity, id) = parser.current_record();
+                        parser.skip_log().record(entity, id, "error")?;
+                        if errors > opt.max_errors {
+                            if opt.max_errors > 0 {
+                                error!("Aborting after {} errors", errors);
+                            }
+                            return Err(Box::new(e));
+                        }
+                        warn!("Skipping error {} of {}: {}", errors, opt.max_errors, e);
+                        parser.discard_record();
+                        skipping = depth > 1;
+                    }
+                }
             };
             buf.clear();
+            if opt.parseropts.limit.is_some_and(|limit| parser.records() >= limit) {
+                info!("Stopping after {} records", parser.records());
+                parser.finish()?;
+                break;
+            }
         }
     }
 
+    if errors > 0 {
+        warn!("Finished with {} skipped errors", errors);
+    }
+
+    if opt.dbopts.dry_run {
+        return Ok(());
+    }
+
     if opt.dbopts.create_indexes {
         db::indexes(&opt.dbopts, "sql/indexes.sql")?;
     }
 
+    if opt.dbopts.check_main_releases {
+        db::check_main_releases(&opt.dbopts)?;
+    }
+
+    if let Some(path) = &opt.dbopts.report_orphans {
+        db::report_orphans(&opt.dbopts, path)?;
+    }
+
+    if opt.dbopts.post_check {
+        db::post_check(&opt.dbopts, &entities, opt.parseropts.expected_count)?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn a_skipped_error_drops_the_whole_record() {
+        let dir = env::temp_dir().join(format!("discogs-load-malformed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let skip_log = dir.join("skipped.tsv");
+        let opt = Opt::from_iter([
+            "discogs-load",
+            "--quiet",
+            "--backend",
+            "csv",
+            "--out-dir",
+            dir.to_str().unwrap(),
+            "--max-errors",
+            "1",
+            "--skip-log",
+            skip_log.to_str().unwrap(),
+            "test_data/malformed_release.xml",
+        ]);
+        read_files(&opt).unwrap();
+        let rows = |table: &str| -> Vec<String> {
+            let csv = fs::read_to_string(dir.join(format!("{}.csv", table))).unwrap_or_default();
+            csv.lines().skip(1).map(String::from).collect()
+        };
+        let releases = rows("release");
+        assert_eq!(releases.len(), 2);
+        assert!(releases.iter().all(|row| !row.starts_with("2,")));
+        let tracks = rows("track");
+        assert_eq!(tracks.len(), 2);
+        assert!(tracks.iter().all(|row| !row.starts_with("2,")));
+        assert!(rows("release_label").is_empty());
+        assert_eq!(fs::read_to_string(&skip_log).unwrap(), "release\t2\terror\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,11 @@
 use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
+use std::{collections::HashMap, error::Error, mem, str};
 
-use crate::db::{write_masters, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::{Batch, DbOpt, SqlSerialization, WriteError, Writer};
+use crate::parser::{lossy_text, Parser, ParserOpt};
+use crate::skip_log::SkipLog;
 
 #[derive(Clone, Debug)]
 pub struct Master {
@@ -16,6 +17,9 @@ pub struct Master {
     pub genres: Vec<String>,
     pub styles: Vec<String>,
     pub data_quality: String,
+    pub notes_raw: Option<String>,
+    pub title_base: Option<String>,
+    pub title_disambig: Option<i32>,
 }
 
 impl SqlSerialization for Master {
@@ -29,6 +33,9 @@ impl SqlSerialization for Master {
             &self.genres,
             &self.styles,
             &self.data_quality,
+            &self.notes_raw,
+            &self.title_base,
+            &self.title_disambig,
         ];
         row
     }
@@ -45,6 +52,9 @@ impl Master {
             genres: Vec::new(),
             styles: Vec::new(),
             data_quality: String::new(),
+            notes_raw: None,
+            title_base: None,
+            title_disambig: None,
         }
     }
 }
@@ -89,6 +99,10 @@ enum ParserReadState {
     DataQuality,
     Videos,
     Notes,
+    Genres,
+    Genre,
+    Styles,
+    Style,
     // master_artists
     ArtistId,
     ArtistName,
@@ -105,10 +119,13 @@ pub struct MastersParser<'a> {
     master_artists: HashMap<i32, MasterArtist>,
     pb: ProgressBar,
     db_opts: &'a DbOpt,
+    writer: Writer<'a>,
+    skip_log: SkipLog,
+    parser_opts: &'a ParserOpt,
 }
 
 impl<'a> MastersParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+    pub fn new(db_opts: &'a DbOpt, parser_opts: &'a ParserOpt) -> Self {
         MastersParser {
             state: ParserReadState::Master,
             masters: HashMap::new(),
@@ -116,32 +133,31 @@ impl<'a> MastersParser<'a> {
             current_artist: MasterArtist::new(),
             current_master_id: 0,
             master_artists: HashMap::new(),
-            pb: ProgressBar::new(1821993),
+            pb: parser_opts.progress_bar(),
             db_opts,
+            writer: Writer::new(db_opts),
+            skip_log: SkipLog::new(parser_opts.skip_log.clone()),
+            parser_opts,
         }
     }
-}
 
-impl<'a> Parser<'a> for MastersParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        MastersParser {
-            state: ParserReadState::Master,
-            masters: HashMap::new(),
-            current_master: Master::new(),
-            current_artist: MasterArtist::new(),
-            current_master_id: 0,
-            master_artists: HashMap::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
-        }
+    /// Hand everything collected so far to the writer.
+    fn write_batch(&mut self) -> Result<(), WriteError> {
+        self.writer.write(Batch::Masters {
+            masters: mem::take(&mut self.masters),
+            artists: mem::take(&mut self.master_artists),
+        })
     }
+}
+
+impl<'a> Parser for MastersParser<'a> {
     fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
         self.state = match self.state {
             ParserReadState::Master => {
                 match ev {
                     Event::Start(e) if e.local_name() == b"master" => {
-                        self.current_master.genres = Vec::new();
-                        self.current_master.styles = Vec::new();
+                        // nothing carries over from the previous master, e.g. missing <notes>
+                        self.current_master = Master::new();
                         self.current_master.id = str::parse(str::from_utf8(
                             &e.attributes().next().unwrap()?.unescaped_value()?,
                         )?)?;
@@ -156,25 +172,38 @@ impl<'a> Parser<'a> for MastersParser<'a> {
                         b"data_quality" => ParserReadState::DataQuality,
                         b"videos" => ParserReadState::Videos,
                         b"notes" => ParserReadState::Notes,
+                        b"genres" => ParserReadState::Genres,
+                        b"styles" => ParserReadState::Styles,
                         _ => ParserReadState::Master,
                     },
 
                     Event::End(e) if e.local_name() == b"master" => {
-                        self.masters
-                            .entry(self.current_master.id)
-                            .or_insert(self.current_master.clone());
+                        let (base, disambig) = self.parser_opts.split_title(&self.current_master.title);
+                        self.current_master.title_base = base;
+                        self.current_master.title_disambig = disambig;
+                        let skipped = self
+                            .parser_opts
+                            .skip_reason(&self.current_master, &self.current_master.data_quality);
+                        if skipped == Ok(None) {
+                            self.masters
+                                .entry(self.current_master.id)
+                                .or_insert(self.current_master.clone());
+                        } else {
+                            let master_id = self.current_master.id;
+                            self.master_artists.retain(|_, a| a.master_id != master_id);
+                        }
+                        if let Some(reason) = skipped? {
+                            self.skip_log.record("master", self.current_master.id, &reason)?;
+                        }
                         if self.masters.len() >= self.db_opts.batch_size {
-                            write_masters(self.db_opts, &self.masters, &self.master_artists)?;
-                            self.masters = HashMap::new();
-                            self.master_artists = HashMap::new();
+                            self.write_batch()?;
                         }
                         self.pb.inc(1);
                         ParserReadState::Master
                     }
 
                     Event::End(e) if e.local_name() == b"masters" => {
-                        // write to db remainder of masters
-                        write_masters(self.db_opts, &self.masters, &self.master_artists)?;
+                        self.finish()?;
                         ParserReadState::Master
                     }
 
@@ -235,7 +264,8 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistName => match ev {
                 Event::Text(e) => {
-                    self.current_artist.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.name =
+                        lossy_text(&e.unescaped()?, "master", self.current_master.id);
                     ParserReadState::ArtistName
                 }
 
@@ -246,7 +276,8 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistAnv => match ev {
                 Event::Text(e) => {
-                    self.current_artist.anv = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.anv =
+                        lossy_text(&e.unescaped()?, "master", self.current_master.id);
                     ParserReadState::ArtistAnv
                 }
 
@@ -257,7 +288,8 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistRole => match ev {
                 Event::Text(e) => {
-                    self.current_artist.role = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.role =
+                        lossy_text(&e.unescaped()?, "master", self.current_master.id);
                     ParserReadState::ArtistRole
                 }
 
@@ -268,7 +300,8 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::Title => match ev {
                 Event::Text(e) => {
-                    self.current_master.title = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_master.title =
+                        lossy_text(&e.unescaped()?, "master", self.current_master.id);
                     ParserReadState::Title
                 }
 
@@ -279,7 +312,10 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::Notes => match ev {
                 Event::Text(e) => {
-                    self.current_master.notes = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let notes = lossy_text(&e.unescaped()?, "master", self.current_master.id);
+                    let (notes, raw) = self.parser_opts.clean_markup(notes);
+                    self.current_master.notes = notes;
+                    self.current_master.notes_raw = raw;
                     ParserReadState::Notes
                 }
 
@@ -302,7 +338,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
             ParserReadState::DataQuality => match ev {
                 Event::Text(e) => {
                     self.current_master.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                        lossy_text(&e.unescaped()?, "master", self.current_master.id);
                     ParserReadState::DataQuality
                 }
 
@@ -311,6 +347,50 @@ impl<'a> Parser<'a> for MastersParser<'a> {
                 _ => ParserReadState::DataQuality,
             },
 
+            ParserReadState::Genres => match ev {
+                Event::Start(e) if e.local_name() == b"genre" => ParserReadState::Genre,
+
+                Event::End(e) if e.local_name() == b"genres" => ParserReadState::Master,
+
+                _ => ParserReadState::Genres,
+            },
+
+            ParserReadState::Genre => match ev {
+                Event::Text(e) => {
+                    push_unique(
+                        &mut self.current_master.genres,
+                        &lossy_text(&e.unescaped()?, "master", self.current_master.id),
+                    );
+                    ParserReadState::Genre
+                }
+
+                Event::End(e) if e.local_name() == b"genre" => ParserReadState::Genres,
+
+                _ => ParserReadState::Genre,
+            },
+
+            ParserReadState::Styles => match ev {
+                Event::Start(e) if e.local_name() == b"style" => ParserReadState::Style,
+
+                Event::End(e) if e.local_name() == b"styles" => ParserReadState::Master,
+
+                _ => ParserReadState::Styles,
+            },
+
+            ParserReadState::Style => match ev {
+                Event::Text(e) => {
+                    push_unique(
+                        &mut self.current_master.styles,
+                        &lossy_text(&e.unescaped()?, "master", self.current_master.id),
+                    );
+                    ParserReadState::Style
+                }
+
+                Event::End(e) if e.local_name() == b"style" => ParserReadState::Styles,
+
+                _ => ParserReadState::Style,
+            },
+
             ParserReadState::Videos => match ev {
                 Event::End(e) if e.local_name() == b"videos" => ParserReadState::Master,
 
@@ -320,4 +400,95 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
         Ok(())
     }
+
+    fn context(&self) -> String {
+        format!("master {}, field {:?}", self.current_master.id, self.state)
+    }
+
+    fn current_record(&self) -> (&'static str, i32) {
+        ("master", self.current_master.id)
+    }
+
+    fn discard_record(&mut self) {
+        let master_id = self.current_master.id;
+        self.master_artists.retain(|_, a| a.master_id != master_id);
+        self.current_master = Master::new();
+        self.state = ParserReadState::Master;
+    }
+
+    fn skip_log(&mut self) -> &mut SkipLog {
+        &mut self.skip_log
+    }
+
+    fn records(&self) -> u64 {
+        self.pb.position()
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // write to db remainder of masters
+        if !self.masters.is_empty() {
+            self.write_batch()?;
+        }
+        self.writer.finish()?;
+        self.skip_log.log_counts();
+        Ok(())
+    }
+}
+
+/// Append a trimmed value, keeping document order and skipping blanks and repeats.
+fn push_unique(values: &mut Vec<String>, value: &str) {
+    let value = value.trim();
+    if !value.is_empty() && !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::Reader;
+    use structopt::StructOpt;
+
+    /// Feed `<master>` elements through the parser without writing them, so the parsed
+    /// rows are still in the parser. The options live for the rest of the test run.
+    fn parse(xml: &str, args: &[&str]) -> MastersParser<'static> {
+        let mut argv = vec!["discogs-load", "--dry-run", "--quiet"];
+        argv.extend(args);
+        let opt: &'static crate::Opt = Box::leak(Box::new(crate::Opt::from_iter(argv)));
+        let mut parser = MastersParser::new(&opt.dbopts, &opt.parseropts);
+        let mut reader = Reader::from_str(xml);
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event(&mut buf).unwrap() {
+                Event::Eof => break,
+                ev => parser.process(ev).unwrap(),
+            }
+            buf.clear();
+        }
+        parser
+    }
+
+    #[test]
+    fn styles_keep_their_order() {
+        let parser = parse(
+            r#"<master id="1"><title>Selected Ambient Works</title>
+                <genres><genre>Electronic</genre></genres>
+                <styles><style>Ambient</style><style>Techno</style><style>IDM</style></styles>
+            </master>"#,
+            &[],
+        );
+        assert_eq!(parser.masters[&1].styles, ["Ambient", "Techno", "IDM"]);
+    }
+
+    #[test]
+    fn raw_notes_are_not_carried_over() {
+        let parser = parse(
+            r#"<master id="1"><title>With notes</title><notes>[b]Remastered[/b]</notes></master>
+            <master id="2"><title>Without notes</title></master>"#,
+            &["--strip-markup"],
+        );
+        assert_eq!(parser.masters[&1].notes_raw.as_deref(), Some("[b]Remastered[/b]"));
+        assert_eq!(parser.masters[&2].notes_raw, None);
+        assert_eq!(parser.masters[&2].notes, "");
+    }
 }
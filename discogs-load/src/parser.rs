@@ -1,11 +1,323 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::warn;
 use quick_xml::events::Event;
-use std::error::Error;
+use std::{borrow::Cow, error::Error, path::PathBuf};
+use structopt::StructOpt;
 
-use crate::db::DbOpt;
+use crate::checks::{self, parse_check, CheckAction, Checked};
+use crate::db::{
+    FORMAT, RELEASE_ARTIST, RELEASE_COMPANY, RELEASE_EXTRAARTIST, RELEASE_IDENTIFIER,
+    RELEASE_IMAGE, RELEASE_LABEL, RELEASE_VIDEO, TRACK, TRACK_ARTIST,
+};
+use crate::skip_log::SkipLog;
 
-pub trait Parser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self
-    where
-        Self: Sized;
+// Discogs data_quality values, from worst to best
+const DATA_QUALITIES: [&str; 6] = [
+    "Entirely Incorrect",
+    "Needs Major Changes",
+    "Needs Minor Changes",
+    "Needs Vote",
+    "Correct",
+    "Complete and Correct",
+];
+
+// release child tables --skip-tables can leave out
+pub const SKIPPABLE_TABLES: [&str; 10] = [
+    RELEASE_LABEL.name,
+    RELEASE_VIDEO.name,
+    TRACK.name,
+    TRACK_ARTIST.name,
+    FORMAT.name,
+    RELEASE_ARTIST.name,
+    RELEASE_EXTRAARTIST.name,
+    RELEASE_IDENTIFIER.name,
+    RELEASE_COMPANY.name,
+    RELEASE_IMAGE.name,
+];
+
+// BBCode tags that wrap text in notes and profiles
+const BBCODE_TAGS: [&str; 8] = ["b", "i", "u", "s", "url", "img", "quote", "code"];
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ParserOpt {
+    /// Skip releases with fewer tracks than this
+    #[structopt(long = "min-track-count", default_value = "0")]
+    pub min_track_count: usize,
+    /// Don't load release labels
+    #[structopt(long = "no-labels")]
+    pub no_labels: bool,
+    /// Don't load release videos
+    #[structopt(long = "no-videos")]
+    pub no_videos: bool,
+    /// Don't load release tracks
+    #[structopt(long = "no-tracks")]
+    pub no_tracks: bool,
+    /// Don't load release formats
+    #[structopt(long = "no-formats")]
+    pub no_formats: bool,
+    /// Don't parse or load these release child tables, e.g. release_video,format,track
+    #[structopt(long = "skip-tables", use_delimiter = true)]
+    pub skip_tables: Vec<String>,
+    /// Skip records with a lower data quality, e.g. "Correct"
+    #[structopt(long = "min-quality", parse(try_from_str = parse_quality))]
+    pub min_quality: Option<usize>,
+    /// Strip BBCode and HTML tags from notes and profiles, keeping the raw text alongside
+    #[structopt(long = "strip-markup")]
+    pub strip_markup: bool,
+    /// Report the genres, styles and countries seen once the releases are loaded
+    #[structopt(long = "summarize-facets")]
+    pub summarize_facets: bool,
+    /// Write releases removed by the filters to release_rejected instead of dropping them
+    #[structopt(long = "keep-rejected")]
+    pub keep_rejected: bool,
+    /// Fill format weight_grams and size_inches from descriptions like "180g" and '12"'
+    #[structopt(long = "format-hints")]
+    pub format_hints: bool,
+    /// Most progress bar redraws per second
+    #[structopt(long = "progress-hz", default_value = "15")]
+    pub progress_hz: u64,
+    /// Show progress as compressed bytes read rather than records parsed, where the size is known
+    #[structopt(long = "byte-progress")]
+    pub byte_progress: bool,
+    /// Stop each file after this many records, e.g. for a small sample database
+    #[structopt(long = "limit")]
+    pub limit: Option<u64>,
+    /// Don't show a progress bar, e.g. when logging to a file
+    #[structopt(long = "quiet")]
+    pub quiet: bool,
+    /// Number of records in the dump, for the progress bar total
+    #[structopt(long = "expected-count")]
+    pub expected_count: Option<u64>,
+    /// Copy each release's first label and catno onto the release row
+    #[structopt(long = "primary-label")]
+    pub primary_label: bool,
+    /// Write a line with the entity, id and reason for every skipped record to this file
+    #[structopt(long = "skip-log", parse(from_os_str))]
+    pub skip_log: Option<PathBuf>,
+    /// Split a trailing " (2)" style disambiguation off release and master titles
+    #[structopt(long = "split-titles")]
+    pub split_titles: bool,
+    /// Only load releases from this country, can be repeated
+    #[structopt(long = "filter-country", number_of_values = 1)]
+    pub filter_country: Vec<String>,
+    /// Only load releases with one of these genres, can be repeated
+    #[structopt(long = "filter-genre", number_of_values = 1)]
+    pub filter_genre: Vec<String>,
+    /// Only load releases with one of these styles, can be repeated
+    #[structopt(long = "filter-style", number_of_values = 1)]
+    pub filter_style: Vec<String>,
+    /// Only load releases flagged as the main release of their master
+    #[structopt(long = "main-releases-only")]
+    pub main_releases_only: bool,
+    /// Check each record, e.g. release.title-non-empty (see checks.rs for the list)
+    #[structopt(long = "check", number_of_values = 1, parse(try_from_str = parse_check))]
+    pub checks: Vec<String>,
+    /// What a record failing a --check does: warn, skip or abort
+    #[structopt(long = "check-action", default_value = "warn", possible_values = &["warn", "skip", "abort"])]
+    pub check_action: CheckAction,
+}
+
+impl ParserOpt {
+    /// Whether a release child table is left out by --skip-tables or its --no-* flag.
+    pub fn skips(&self, table: &str) -> bool {
+        let flag = match table {
+            "release_label" => self.no_labels,
+            "release_video" => self.no_videos,
+            "track" => self.no_tracks,
+            "format" => self.no_formats,
+            _ => false,
+        };
+        flag || self.skip_tables.iter().any(|t| t == table)
+    }
+
+    /// Whether a record with this data_quality passes --min-quality.
+    pub fn keeps_quality(&self, data_quality: &str) -> bool {
+        match self.min_quality {
+            Some(min) => quality_rank(data_quality).is_some_and(|rank| rank >= min),
+            None => true,
+        }
+    }
+
+    /// Why a finished record is filtered out by --min-quality or --check, if it is.
+    /// Errors when a check fails under --check-action abort.
+    pub fn skip_reason<T: Checked>(
+        &self,
+        record: &T,
+        data_quality: &str,
+    ) -> Result<Option<String>, String> {
+        if !self.keeps_quality(data_quality) {
+            return Ok(Some(String::from("min-quality")));
+        }
+        if self.checks.is_empty() {
+            return Ok(None);
+        }
+        checks::run(record, &self.checks, self.check_action)
+    }
+
+    /// A progress bar over --expected-count records, or a running count without it.
+    /// Hidden when --byte-progress has a byte count bar instead.
+    pub fn progress_bar(&self) -> ProgressBar {
+        if self.byte_progress || self.quiet {
+            return ProgressBar::hidden();
+        }
+        match self.expected_count {
+            Some(len) => {
+                let pb = self.throttled_bar(len);
+                pb.set_style(ProgressStyle::default_bar().template(
+                    "[{elapsed_precise}] {bar:40} {pos}/{len} ({percent}%) {per_sec} ETA {eta}",
+                ));
+                pb
+            }
+            None => {
+                let pb = ProgressBar::new_spinner();
+                pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(self.progress_hz));
+                pb.set_style(
+                    ProgressStyle::default_spinner().template("[{elapsed_precise}] {pos} records ({per_sec})"),
+                );
+                pb
+            }
+        }
+    }
+
+    /// A progress bar over the `len` bytes of a file, for --byte-progress.
+    pub fn byte_progress_bar(&self, len: u64) -> ProgressBar {
+        if self.quiet {
+            return ProgressBar::hidden();
+        }
+        let pb = self.throttled_bar(len);
+        pb.set_style(
+            ProgressStyle::default_bar().template(
+                "[{elapsed_precise}] {bar:40} {bytes}/{total_bytes} ({percent}%) {bytes_per_sec} ETA {eta}",
+            ),
+        );
+        pb
+    }
+
+    /// Redrawn at most --progress-hz times a second.
+    fn throttled_bar(&self, len: u64) -> ProgressBar {
+        let pb = ProgressBar::new(len);
+        pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(self.progress_hz));
+        pb
+    }
+
+    /// Apply --split-titles, returning the base title and disambiguation number.
+    pub fn split_title(&self, title: &str) -> (Option<String>, Option<i32>) {
+        if !self.split_titles {
+            return (None, None);
+        }
+        match split_disambiguation(title) {
+            Some((base, number)) => (Some(base.to_string()), Some(number)),
+            None => (Some(title.to_string()), None),
+        }
+    }
+
+    /// Apply --strip-markup to notes or profile text, returning the text to store and the raw text.
+    pub fn clean_markup(&self, text: String) -> (String, Option<String>) {
+        if self.strip_markup {
+            (strip_markup(&text), Some(text))
+        } else {
+            (text, None)
+        }
+    }
+}
+
+/// Free text from the dump, with any invalid UTF-8 replaced and logged rather than failing the run.
+pub fn lossy_text(bytes: &[u8], entity: &str, id: i32) -> String {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(text) => text.to_string(),
+        Cow::Owned(text) => {
+            warn!("{} {}: replaced invalid UTF-8 in {:?}", entity, id, text);
+            text
+        }
+    }
+}
+
+/// A count with thousands separators, e.g. 14,231,880.
+pub fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Split "Nirvana (2)" into ("Nirvana", 2). Only small numbers count, so titles
+/// ending in a year like "Live (1994)" are left alone.
+fn split_disambiguation(title: &str) -> Option<(&str, i32)> {
+    let (base, number) = title.strip_suffix(')')?.rsplit_once(" (")?;
+    let is_small_number = (1..=3).contains(&number.len())
+        && !number.starts_with('0')
+        && number.bytes().all(|b| b.is_ascii_digit());
+    if is_small_number && !base.trim().is_empty() {
+        Some((base, number.parse().ok()?))
+    } else {
+        None
+    }
+}
+
+/// Remove markup tags from text, keeping the text they wrap.
+fn strip_markup(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['[', '<']) {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let close = if rest.starts_with('[') { ']' } else { '>' };
+        match rest.find(close) {
+            Some(end) if is_markup_tag(&rest[1..end], close) => rest = &rest[end + 1..],
+            _ => {
+                plain.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// Whether the contents of a `[...]` or `<...>` look like a tag rather than plain text.
+fn is_markup_tag(tag: &str, close: char) -> bool {
+    let name = tag.strip_prefix('/').unwrap_or(tag);
+    if close == '>' {
+        return name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '!');
+    }
+    let name = name.split('=').next().unwrap_or_default().to_ascii_lowercase();
+    // Discogs links to other entities as [a123], [l123], [r123] and [m123]
+    let is_link = name.len() > 1
+        && name.starts_with(|c| "almr".contains(c))
+        && name[1..].bytes().all(|b| b.is_ascii_digit());
+    is_link || BBCODE_TAGS.contains(&name.as_str())
+}
+
+fn quality_rank(data_quality: &str) -> Option<usize> {
+    match data_quality {
+        "Entirely Incorrect Edit" => Some(0),
+        q => DATA_QUALITIES.iter().position(|&known| known == q),
+    }
+}
+
+pub fn parse_quality(s: &str) -> Result<usize, String> {
+    quality_rank(s).ok_or_else(|| format!("expected one of: {}", DATA_QUALITIES.join(", ")))
+}
+
+pub trait Parser {
     fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>>;
+    /// Describes the record and field being parsed, for error reports.
+    fn context(&self) -> String;
+    /// The entity and id of the record being parsed.
+    fn current_record(&self) -> (&'static str, i32);
+    /// Drops what has been parsed of the current record, after an error that is skipped.
+    /// The caller doesn't pass on the rest of the record's events.
+    fn discard_record(&mut self);
+    /// The --skip-log of the parser, which also takes the records skipped after an error
+    /// so they show in its counts.
+    fn skip_log(&mut self) -> &mut SkipLog;
+    /// Records read so far, for --limit.
+    fn records(&self) -> u64;
+    /// Writes any records not yet flushed, at the root closing tag or the end of the file.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>>;
 }
@@ -1,18 +1,314 @@
 use indicatif::ProgressBar;
+use log::info;
 use postgres::types::ToSql;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 use std::collections::BTreeMap;
-use std::{collections::HashMap, error::Error, str};
+use std::{collections::HashMap, convert::TryFrom, error::Error, mem, str};
 
-use crate::db::{write_releases, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::{
+    Batch, DbOpt, ReleaseBatch, SqlSerialization, Writer, FORMAT, RELEASE_ARTIST, RELEASE_COMPANY,
+    RELEASE_EXTRAARTIST, RELEASE_IDENTIFIER, RELEASE_IMAGE, RELEASE_LABEL, RELEASE_VIDEO, TRACK,
+    TRACK_ARTIST,
+};
+use crate::parser::{lossy_text, Parser, ParserOpt};
+use crate::skip_log::SkipLog;
 
 #[derive(Clone, Debug)]
 pub struct Track {
     position: String,
     title: String,
     duration: String,
+    duration_seconds: Option<i32>,
+    track_type: String,
+    // 1-based place in the release tracklist, sub-tracks counted after their index track
+    sequence: i32,
+    parent_sequence: Option<i32>,
     release_id: i32,
+    denormalized: Option<Denormalized>,
+}
+
+// the parent release fields copied onto child rows under --denormalize
+#[derive(Clone, Debug)]
+pub struct Denormalized {
+    release_title: String,
+    release_artist_display: String,
+}
+
+impl Denormalized {
+    // appended last, to match the DENORMALIZED columns
+    fn add_to<'a>(denormalized: &'a Option<Denormalized>, row: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        if let Some(d) = denormalized {
+            row.push(&d.release_title);
+            row.push(&d.release_artist_display);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReleaseArtist {
+    release_id: i32,
+    artist_id: Option<i32>,
+    name: String,
+    anv: String,
+    join: String,
+    role: String,
+}
+
+impl ReleaseArtist {
+    fn new(release_id: i32) -> ReleaseArtist {
+        ReleaseArtist {
+            release_id,
+            artist_id: None,
+            name: String::new(),
+            anv: String::new(),
+            join: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+impl SqlSerialization for ReleaseArtist {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.artist_id,
+            &self.name,
+            &self.anv,
+            &self.join,
+            &self.role,
+        ];
+        row
+    }
+}
+
+// a track's own artists, on compilations, with the join to the next one like "feat."
+#[derive(Clone, Debug)]
+pub struct TrackArtist {
+    release_id: i32,
+    track_sequence: i32,
+    artist_id: Option<i32>,
+    name: String,
+    anv: String,
+    join: String,
+    role: String,
+}
+
+impl TrackArtist {
+    fn new(release_id: i32, track_sequence: i32) -> TrackArtist {
+        TrackArtist {
+            release_id,
+            track_sequence,
+            artist_id: None,
+            name: String::new(),
+            anv: String::new(),
+            join: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+impl SqlSerialization for TrackArtist {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.track_sequence,
+            &self.artist_id,
+            &self.name,
+            &self.anv,
+            &self.join,
+            &self.role,
+        ];
+        row
+    }
+}
+
+// one per credit, an artist with several roles is listed several times
+#[derive(Clone, Debug)]
+pub struct ExtraArtist {
+    release_id: i32,
+    artist_id: Option<i32>,
+    name: String,
+    anv: String,
+    role: String,
+}
+
+impl ExtraArtist {
+    fn new(release_id: i32) -> ExtraArtist {
+        ExtraArtist {
+            release_id,
+            artist_id: None,
+            name: String::new(),
+            anv: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+impl SqlSerialization for ExtraArtist {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.artist_id,
+            &self.name,
+            &self.anv,
+            &self.role,
+        ];
+        row
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Identifier {
+    release_id: i32,
+    identifier_type: String,
+    value: String,
+    description: Option<String>,
+    denormalized: Option<Denormalized>,
+}
+
+impl SqlSerialization for Identifier {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let mut row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.identifier_type,
+            &self.value,
+            &self.description,
+        ];
+        Denormalized::add_to(&self.denormalized, &mut row);
+        row
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReleaseCompany {
+    release_id: i32,
+    company_id: Option<i32>,
+    name: String,
+    entity_type_name: String,
+}
+
+impl ReleaseCompany {
+    fn new(release_id: i32) -> ReleaseCompany {
+        ReleaseCompany {
+            release_id,
+            company_id: None,
+            name: String::new(),
+            entity_type_name: String::new(),
+        }
+    }
+}
+
+impl SqlSerialization for ReleaseCompany {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.company_id,
+            &self.name,
+            &self.entity_type_name,
+        ];
+        row
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReleaseImage {
+    release_id: i32,
+    image_type: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    uri: String,
+}
+
+impl SqlSerialization for ReleaseImage {
+    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.image_type,
+            &self.width,
+            &self.height,
+            &self.uri,
+        ];
+        row
+    }
+}
+
+/// Total seconds of an "m:ss" or "h:mm:ss" duration, None if it isn't one.
+fn duration_seconds(duration: &str) -> Option<i32> {
+    let mut seconds: u32 = 0;
+    for part in duration.trim().split(':') {
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    // None rather than wrapping when it's too long for the column
+    i32::try_from(seconds).ok()
+}
+
+/// The year of a released date, "1998", "1998-05" or "1998-05-12", None for "0000-00-00",
+/// an empty date or one that doesn't start with a year.
+fn released_year(released: &str) -> Option<i32> {
+    let year = released.trim().get(..4)?;
+    if !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match year.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(year) => Some(year),
+    }
+}
+
+/// A number that may be left empty, None (NULL) rather than 0 when it is.
+fn optional_number(text: &str) -> Result<Option<i32>, Box<dyn Error>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(text.parse()?))
+}
+
+/// The value of the attribute named `key`, wherever it is in the element.
+fn attribute(e: &BytesStart, key: &[u8]) -> Result<Option<String>, Box<dyn Error>> {
+    for a in e.attributes() {
+        let a = a?;
+        if a.key == key {
+            return Ok(Some(str::from_utf8(&a.unescaped_value()?)?.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Like `attribute`, for free text: invalid UTF-8 is replaced and logged rather than
+/// failing the release.
+fn text_attribute(
+    e: &BytesStart,
+    key: &[u8],
+    release_id: i32,
+) -> Result<Option<String>, Box<dyn Error>> {
+    for a in e.attributes() {
+        let a = a?;
+        if a.key == key {
+            return Ok(Some(lossy_text(
+                &a.unescaped_value()?,
+                "release",
+                release_id,
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Like `attribute`, but a missing attribute is an error naming the release.
+fn required_attribute(
+    e: &BytesStart,
+    key: &[u8],
+    release_id: i32,
+) -> Result<String, Box<dyn Error>> {
+    attribute(e, key)?.ok_or_else(|| {
+        format!(
+            "release {}: missing {} attribute",
+            release_id,
+            String::from_utf8_lossy(key)
+        )
+        .into()
+    })
 }
 
 #[derive(Clone, Debug)]
@@ -20,48 +316,87 @@ pub struct Format {
     name: String,
     qty: String,
     text: String,
+    descriptions: Vec<String>,
     release_id: i32,
-    // TODO Descriptions
+    weight_grams: Option<i32>,
+    size_inches: Option<i32>,
+    denormalized: Option<Denormalized>,
 }
 
 impl Format {
     fn new(release_id: i32, name: String, qty: String, text: String) -> Format {
-        Format { name, qty, text, release_id }
-    }    
+        Format {
+            name,
+            qty,
+            text,
+            descriptions: Vec::new(),
+            release_id,
+            weight_grams: None,
+            size_inches: None,
+            denormalized: None,
+        }
+    }
+
+    /// Pick up a vinyl weight ("180g") or size ('12"') from a format description.
+    fn add_hints(&mut self, description: &str) {
+        let description = description.trim().to_ascii_lowercase();
+        if let Some(size) = description.strip_suffix('"') {
+            self.size_inches = size.trim().parse().ok().or(self.size_inches);
+            return;
+        }
+        let digits = description.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+        let (weight, unit) = description.split_at(digits);
+        if matches!(unit.trim(), "g" | "gram" | "grams") {
+            self.weight_grams = weight.parse().ok().or(self.weight_grams);
+        }
+    }
 }
 
 impl SqlSerialization for Format {
     fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+        let mut row: Vec<&'_ (dyn ToSql + Sync)> = vec![
             &self.release_id,
             &self.name,
             &self.qty,
             &self.text,
+            &self.descriptions,
+            &self.weight_grams,
+            &self.size_inches,
         ];
+        Denormalized::add_to(&self.denormalized, &mut row);
         row
     }
 }
 
-
 impl Track {
-    fn new(release_id: i32) -> Track {
+    fn new(release_id: i32, sequence: i32, parent_sequence: Option<i32>) -> Track {
         Track {
             release_id,
             position: String::new(),
             title: String::new(),
             duration: String::new(),
+            duration_seconds: None,
+            track_type: String::from("track"),
+            sequence,
+            parent_sequence,
+            denormalized: None,
         }
     }
 }
 
 impl SqlSerialization for Track {
     fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+        let mut row: Vec<&'_ (dyn ToSql + Sync)> = vec![
             &self.release_id,
             &self.title,
             &self.position,
             &self.duration,
+            &self.duration_seconds,
+            &self.track_type,
+            &self.sequence,
+            &self.parent_sequence,
         ];
+        Denormalized::add_to(&self.denormalized, &mut row);
         row
     }
 }
@@ -73,14 +408,22 @@ pub struct Release {
     pub title: String,
     pub country: String,
     pub released: String,
+    // the leading year of released, None when it's missing or 0000
+    pub released_year: Option<i32>,
     pub notes: String,
     pub genres: Vec<String>,
     pub styles: Vec<String>,
-    pub master_id: i32,
+    pub master_id: Option<i32>,
     pub data_quality: String,
+    pub notes_raw: Option<String>,
+    pub title_base: Option<String>,
+    pub title_disambig: Option<i32>,
+    pub primary_label: Option<String>,
+    pub primary_catno: Option<String>,
+    // from <master_id is_main_release="true">, only used for filtering
+    pub is_main_release: bool,
 }
 
-
 impl SqlSerialization for Release {
     fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
         let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
@@ -89,11 +432,17 @@ impl SqlSerialization for Release {
             &self.title,
             &self.country,
             &self.released,
+            &self.released_year,
             &self.notes,
             &self.genres,
             &self.styles,
             &self.master_id,
             &self.data_quality,
+            &self.notes_raw,
+            &self.primary_label,
+            &self.primary_catno,
+            &self.title_base,
+            &self.title_disambig,
         ];
         row
     }
@@ -118,15 +467,21 @@ impl SqlSerialization for ReleaseLabel {
 #[derive(Clone, Debug)]
 pub struct ReleaseVideo {
     pub release_id: i32,
-    pub duration: i32,
+    pub duration: Option<i32>,
     pub src: String,
     pub title: String,
+    pub embed: bool,
 }
 
 impl SqlSerialization for ReleaseVideo {
     fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> =
-            vec![&self.release_id, &self.duration, &self.src, &self.title];
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &self.release_id,
+            &self.duration,
+            &self.src,
+            &self.title,
+            &self.embed,
+        ];
         row
     }
 }
@@ -139,15 +494,65 @@ impl Release {
             title: String::new(),
             country: String::new(),
             released: String::new(),
+            released_year: None,
             notes: String::new(),
             genres: Vec::new(),
             styles: Vec::new(),
-            master_id: 0,
+            master_id: None,
             data_quality: String::new(),
+            notes_raw: None,
+            title_base: None,
+            title_disambig: None,
+            primary_label: None,
+            primary_catno: None,
+            is_main_release: false,
         }
     }
 }
 
+/// How often each genre, style and country occurs across the loaded releases.
+#[derive(Default)]
+struct Facets {
+    genres: HashMap<String, u64>,
+    styles: HashMap<String, u64>,
+    countries: HashMap<String, u64>,
+}
+
+impl Facets {
+    fn add(&mut self, release: &Release) {
+        for genre in &release.genres {
+            *self.genres.entry(genre.clone()).or_insert(0) += 1;
+        }
+        for style in &release.styles {
+            *self.styles.entry(style.clone()).or_insert(0) += 1;
+        }
+        if !release.country.is_empty() {
+            *self.countries.entry(release.country.clone()).or_insert(0) += 1;
+        }
+    }
+
+    fn log(&self) {
+        info!("Genres: {}", summarize(&self.genres));
+        info!("Styles: {}", summarize(&self.styles));
+        info!("Countries: {}", summarize(&self.countries));
+    }
+}
+
+/// List the values by descending count, e.g. "Electronic (5M), Rock (4M)".
+fn summarize(counts: &HashMap<String, u64>) -> String {
+    let mut counts: Vec<_> = counts.iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(value, &count)| match count {
+            c if c >= 1_000_000 => format!("{} ({}M)", value, c / 1_000_000),
+            c if c >= 1_000 => format!("{} ({}k)", value, c / 1_000),
+            c => format!("{} ({})", value, c),
+        })
+        .collect();
+    counts.join(", ")
+}
+
 #[derive(Copy, Clone, Debug)]
 enum ParserReadState {
     // release
@@ -166,90 +571,346 @@ enum ParserReadState {
     Labels,
     // release_video
     Videos,
+    Video,
+    VideoTitle,
 
     TrackList,
     Track,
     TrackPosition,
     TrackTitle,
     TrackDuration,
-
-    Images,
+    SubTracks,
+    SubTracksEnd,
+    // track_artist
+    TrackArtists,
+    TrackArtist,
+    TrackArtistId,
+    TrackArtistName,
+    TrackArtistAnv,
+    TrackArtistJoin,
+    TrackArtistRole,
+
+    // release_artist
     Artists,
+    Artist,
+    ArtistId,
+    ArtistName,
+    ArtistAnv,
+    ArtistJoin,
+    ArtistRole,
+    // release_extraartist
     ExtraArtists,
-    Formats,
-    Format,
+    ExtraArtist,
+    ExtraArtistId,
+    ExtraArtistName,
+    ExtraArtistAnv,
+    ExtraArtistRole,
+    // release_identifier
     Identifiers,
+    // release_company
     Companies,
+    Company,
+    CompanyId,
+    CompanyName,
+    CompanyEntityTypeName,
+    // release_image
+    Images,
+    Formats,
+    Format,
+    FormatDescription,
+    // a subtree that isn't loaded
+    Skip,
 }
 
 pub struct ReleasesParser<'a> {
     state: ParserReadState,
     releases: HashMap<i32, Release>,
+    rejected: HashMap<i32, Release>,
     current_release: Release,
     current_id: i32,
-    release_labels: HashMap<i32, ReleaseLabel>,
+    current_label_id: i32,
+    release_labels: BTreeMap<i32, ReleaseLabel>,
     current_video_id: i32,
-    release_videos: HashMap<i32, ReleaseVideo>,
+    release_videos: BTreeMap<i32, ReleaseVideo>,
     current_track_id: i32,
+    // the index track while reading its sub-tracks
+    parent_track_id: Option<i32>,
     tracks: BTreeMap<i32, Track>,
     current_format_id: i32,
     formats: BTreeMap<i32, Format>,
+    current_artist_id: i32,
+    release_artists: BTreeMap<i32, ReleaseArtist>,
+    current_extra_artist_id: i32,
+    extra_artists: BTreeMap<i32, ExtraArtist>,
+    current_identifier_id: i32,
+    identifiers: BTreeMap<i32, Identifier>,
+    current_company_id: i32,
+    companies: BTreeMap<i32, ReleaseCompany>,
+    current_image_id: i32,
+    images: BTreeMap<i32, ReleaseImage>,
+    current_track_artist_id: i32,
+    track_artists: BTreeMap<i32, TrackArtist>,
+    // child ids at the start of the current release, to drop it when filtered
+    first_label_id: i32,
+    first_video_id: i32,
+    first_track_id: i32,
+    first_format_id: i32,
+    first_artist_id: i32,
+    first_extra_artist_id: i32,
+    first_identifier_id: i32,
+    first_company_id: i32,
+    first_image_id: i32,
+    first_track_artist_id: i32,
+    skip_depth: usize,
+    // text read since the last batch, for --max-batch-bytes
+    batch_bytes: usize,
+    // with --resume, the releases up to this id were loaded by an earlier run
+    resume_after: Option<i32>,
+    facets: Option<Facets>,
     pb: ProgressBar,
     db_opts: &'a DbOpt,
+    writer: Writer<'a>,
+    skip_log: SkipLog,
+    parser_opts: &'a ParserOpt,
 }
 
 impl<'a> ReleasesParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
-        ReleasesParser {
+    pub fn new(db_opts: &'a DbOpt, parser_opts: &'a ParserOpt) -> anyhow::Result<Self> {
+        Ok(ReleasesParser {
             state: ParserReadState::Release,
             releases: HashMap::new(),
+            rejected: HashMap::new(),
             current_release: Release::new(0),
             current_id: 0,
-            release_labels: HashMap::new(),
+            current_label_id: 0,
+            release_labels: BTreeMap::new(),
             current_video_id: 0,
-            release_videos: HashMap::new(),
+            release_videos: BTreeMap::new(),
             current_track_id: 0,
+            parent_track_id: None,
             tracks: BTreeMap::new(),
             current_format_id: 0,
             formats: BTreeMap::new(),
-            pb: ProgressBar::new(14976967), // https://api.discogs.com/
+            current_artist_id: 0,
+            release_artists: BTreeMap::new(),
+            current_extra_artist_id: 0,
+            extra_artists: BTreeMap::new(),
+            current_identifier_id: 0,
+            identifiers: BTreeMap::new(),
+            current_company_id: 0,
+            companies: BTreeMap::new(),
+            current_image_id: 0,
+            images: BTreeMap::new(),
+            current_track_artist_id: 0,
+            track_artists: BTreeMap::new(),
+            first_label_id: 0,
+            first_video_id: 0,
+            first_track_id: 0,
+            first_format_id: 0,
+            first_artist_id: 0,
+            first_extra_artist_id: 0,
+            first_identifier_id: 0,
+            first_company_id: 0,
+            first_image_id: 0,
+            first_track_artist_id: 0,
+            skip_depth: 0,
+            batch_bytes: 0,
+            resume_after: db_opts.resume_after()?,
+            facets: parser_opts.summarize_facets.then(Facets::default),
+            pb: parser_opts.progress_bar(),
             db_opts,
+            writer: Writer::new(db_opts),
+            skip_log: SkipLog::new(parser_opts.skip_log.clone()),
+            parser_opts,
+        })
+    }
+
+    /// Why the release that just ended is filtered out, if it is.
+    fn skip_reason(&self) -> Result<Option<String>, String> {
+        // headings and index tracks aren't tracks of their own
+        let track_count = self
+            .tracks
+            .range(self.first_track_id..)
+            .filter(|(_, track)| track.track_type == "track")
+            .count();
+        if !self.parser_opts.skips(TRACK.name) && track_count < self.parser_opts.min_track_count {
+            return Ok(Some(String::from("min-track-count")));
+        }
+        if self.parser_opts.main_releases_only && !self.current_release.is_main_release {
+            return Ok(Some(String::from("main-releases-only")));
+        }
+        let countries = &self.parser_opts.filter_country;
+        if !countries.is_empty() && !countries.contains(&self.current_release.country) {
+            return Ok(Some(String::from("filter-country")));
+        }
+        // genres and styles are complete only now, at the end of the release
+        let genres = &self.parser_opts.filter_genre;
+        if !genres.is_empty()
+            && !self
+                .current_release
+                .genres
+                .iter()
+                .any(|g| genres.contains(g))
+        {
+            return Ok(Some(String::from("filter-genre")));
+        }
+        let styles = &self.parser_opts.filter_style;
+        if !styles.is_empty()
+            && !self
+                .current_release
+                .styles
+                .iter()
+                .any(|s| styles.contains(s))
+        {
+            return Ok(Some(String::from("filter-style")));
         }
+        self.parser_opts
+            .skip_reason(&self.current_release, &self.current_release.data_quality)
     }
-}
 
-impl<'a> Parser<'a> for ReleasesParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        ReleasesParser {
-            state: ParserReadState::Release,
-            releases: HashMap::new(),
-            current_release: Release::new(0),
-            current_id: 0,
-            release_labels: HashMap::new(),
-            current_video_id: 0,
-            release_videos: HashMap::new(),
-            current_track_id: 0,
-            tracks: BTreeMap::new(),
-            current_format_id: 0,
-            formats: BTreeMap::new(),
-            pb: ProgressBar::new(14976967), // https://api.discogs.com/
-            db_opts,
+    fn skip_subtree(&mut self) -> ParserReadState {
+        self.skip_depth = 1;
+        ParserReadState::Skip
+    }
+
+    /// The track being read, created on its first field.
+    fn current_track(&mut self) -> &mut Track {
+        let sequence = |id: i32| id - self.first_track_id + 1;
+        let track = Track::new(
+            self.current_id,
+            sequence(self.current_track_id),
+            self.parent_track_id.map(sequence),
+        );
+        self.tracks.entry(self.current_track_id).or_insert(track)
+    }
+
+    /// Copy parent release fields onto the child rows of the release that just ended.
+    fn denormalize_current_release(&mut self) {
+        // the artists as credited, e.g. "Simon & Garfunkel" or "Foo, Bar"
+        let mut artist_display = String::new();
+        for artist in self
+            .release_artists
+            .range(self.first_artist_id..)
+            .map(|(_, a)| a)
+        {
+            artist_display.push_str(if artist.anv.is_empty() {
+                &artist.name
+            } else {
+                &artist.anv
+            });
+            match artist.join.as_str() {
+                "" => {}
+                "," => artist_display.push_str(", "),
+                join => {
+                    artist_display.push(' ');
+                    artist_display.push_str(join);
+                    artist_display.push(' ');
+                }
+            }
+        }
+        let denormalized = Denormalized {
+            release_title: self.current_release.title.clone(),
+            release_artist_display: artist_display.trim_end().to_string(),
+        };
+        for track in self.tracks.range_mut(self.first_track_id..).map(|(_, t)| t) {
+            track.denormalized = Some(denormalized.clone());
         }
+        for format in self
+            .formats
+            .range_mut(self.first_format_id..)
+            .map(|(_, f)| f)
+        {
+            format.denormalized = Some(denormalized.clone());
+        }
+        for identifier in self
+            .identifiers
+            .range_mut(self.first_identifier_id..)
+            .map(|(_, i)| i)
+        {
+            identifier.denormalized = Some(denormalized.clone());
+        }
+    }
+
+    /// Hand everything collected so far to the writer.
+    fn write_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        // releases come in id order, so everything up to here is handled
+        let last_id = self.current_id;
+        self.batch_bytes = 0;
+        // drained rather than taken so the parser's hashmaps don't regrow from
+        // empty, the batch still gets a new allocation of its own
+        self.writer.write(Batch::Releases(Box::new(ReleaseBatch {
+            releases: self.releases.drain().collect(),
+            rejected: self.rejected.drain().collect(),
+            labels: mem::take(&mut self.release_labels),
+            videos: mem::take(&mut self.release_videos),
+            tracks: mem::take(&mut self.tracks),
+            formats: mem::take(&mut self.formats),
+            artists: mem::take(&mut self.release_artists),
+            extra_artists: mem::take(&mut self.extra_artists),
+            identifiers: mem::take(&mut self.identifiers),
+            companies: mem::take(&mut self.companies),
+            images: mem::take(&mut self.images),
+            track_artists: mem::take(&mut self.track_artists),
+        })))?;
+        self.db_opts.write_checkpoint(last_id)?;
+        Ok(())
+    }
+
+    /// Drop everything collected for the release that just ended.
+    fn discard_current_release(&mut self) {
+        self.release_labels.split_off(&self.first_label_id);
+        self.release_videos.split_off(&self.first_video_id);
+        self.tracks.split_off(&self.first_track_id);
+        self.formats.split_off(&self.first_format_id);
+        self.release_artists.split_off(&self.first_artist_id);
+        self.extra_artists.split_off(&self.first_extra_artist_id);
+        self.identifiers.split_off(&self.first_identifier_id);
+        self.companies.split_off(&self.first_company_id);
+        self.images.split_off(&self.first_image_id);
+        self.track_artists.split_off(&self.first_track_artist_id);
     }
+}
 
+impl<'a> Parser for ReleasesParser<'a> {
     fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+        if let Event::Text(e) = &ev {
+            // the text is most of what a batch holds in memory
+            self.batch_bytes += e.len();
+        }
         self.state = match self.state {
             ParserReadState::Release => {
                 match ev {
                     Event::Start(e) if e.local_name() == b"release" => {
-                        self.current_id = str::parse(str::from_utf8(
-                            &e.attributes().next().unwrap()?.unescaped_value()?,
-                        )?)?;
-                        self.current_release = Release::new(self.current_id);
-                        self.current_release.status = str::parse(str::from_utf8(
-                            &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                        )?)?;
-                        ParserReadState::Release
+                        // before anything can fail, so discard_record only drops this release
+                        self.first_label_id = self.current_label_id;
+                        self.first_video_id = self.current_video_id;
+                        self.first_track_id = self.current_track_id;
+                        self.parent_track_id = None;
+                        self.first_format_id = self.current_format_id;
+                        self.first_artist_id = self.current_artist_id;
+                        self.first_extra_artist_id = self.current_extra_artist_id;
+                        self.first_identifier_id = self.current_identifier_id;
+                        self.first_company_id = self.current_company_id;
+                        self.first_image_id = self.current_image_id;
+                        self.first_track_artist_id = self.current_track_artist_id;
+                        let id = attribute(&e, b"id")?.ok_or_else(|| {
+                            format!("release after {}: missing id attribute", self.current_id)
+                        })?;
+                        self.current_id = str::parse(&id)?;
+                        if self
+                            .resume_after
+                            .is_some_and(|after| self.current_id <= after)
+                        {
+                            // loaded by the run being resumed
+                            self.pb.inc(1);
+                            self.skip_subtree()
+                        } else {
+                            self.current_release = Release::new(self.current_id);
+                            // older dumps have releases without a status
+                            self.current_release.status =
+                                attribute(&e, b"status")?.unwrap_or_default();
+                            ParserReadState::Release
+                        }
                     }
 
                     Event::Start(e) => match e.local_name() {
@@ -259,55 +920,92 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                         b"notes" => ParserReadState::Notes,
                         b"genres" => ParserReadState::Genres,
                         b"styles" => ParserReadState::Styles,
-                        b"master_id" => ParserReadState::MasterId,
+                        b"master_id" => {
+                            self.current_release.is_main_release = e
+                                .attributes()
+                                .flatten()
+                                .any(|a| a.key == b"is_main_release" && &*a.value == b"true");
+                            ParserReadState::MasterId
+                        }
                         b"data_quality" => ParserReadState::DataQuality,
+                        b"labels" if self.parser_opts.skips(RELEASE_LABEL.name) => {
+                            self.skip_subtree()
+                        }
                         b"labels" => ParserReadState::Labels,
+                        b"videos" if self.parser_opts.skips(RELEASE_VIDEO.name) => {
+                            self.skip_subtree()
+                        }
                         b"videos" => ParserReadState::Videos,
+                        b"tracklist" if self.parser_opts.skips(TRACK.name) => self.skip_subtree(),
                         b"tracklist" => ParserReadState::TrackList,
+                        b"images" if self.parser_opts.skips(RELEASE_IMAGE.name) => {
+                            self.skip_subtree()
+                        }
                         b"images" => ParserReadState::Images,
+                        b"artists" if self.parser_opts.skips(RELEASE_ARTIST.name) => {
+                            self.skip_subtree()
+                        }
                         b"artists" => ParserReadState::Artists,
+                        b"extraartists" if self.parser_opts.skips(RELEASE_EXTRAARTIST.name) => {
+                            self.skip_subtree()
+                        }
                         b"extraartists" => ParserReadState::ExtraArtists,
+                        b"formats" if self.parser_opts.skips(FORMAT.name) => self.skip_subtree(),
                         b"formats" => ParserReadState::Formats,
+                        b"identifiers" if self.parser_opts.skips(RELEASE_IDENTIFIER.name) => {
+                            self.skip_subtree()
+                        }
                         b"identifiers" => ParserReadState::Identifiers,
+                        b"companies" if self.parser_opts.skips(RELEASE_COMPANY.name) => {
+                            self.skip_subtree()
+                        }
                         b"companies" => ParserReadState::Companies,
                         _ => ParserReadState::Release,
                     },
 
                     Event::End(e) if e.local_name() == b"release" => {
-                        self.releases
-                            .entry(self.current_id)
-                            .or_insert(self.current_release.clone());
-                        if self.releases.len() >= self.db_opts.batch_size {
-                            // write to db every 1000 records and clean the hashmaps
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_releases(
-                                self.db_opts,
-                                &self.releases,
-                                &self.release_labels,
-                                &self.release_videos,
-                                &self.tracks,
-                                &self.formats,
-                            )?;
-                            self.releases = HashMap::new();
-                            self.release_labels = HashMap::new();
-                            self.release_videos = HashMap::new();
-                            self.tracks = BTreeMap::new();
-                            self.formats = BTreeMap::new();
+                        let (base, disambig) =
+                            self.parser_opts.split_title(&self.current_release.title);
+                        self.current_release.title_base = base;
+                        self.current_release.title_disambig = disambig;
+                        let skipped = self.skip_reason();
+                        if skipped == Ok(None) {
+                            if self.db_opts.denormalize {
+                                self.denormalize_current_release();
+                            }
+                            if let Some(facets) = &mut self.facets {
+                                facets.add(&self.current_release);
+                            }
+                            self.releases
+                                .entry(self.current_id)
+                                .or_insert(self.current_release.clone());
+                        } else {
+                            self.discard_current_release();
+                            if self.parser_opts.keep_rejected {
+                                self.rejected
+                                    .entry(self.current_id)
+                                    .or_insert(self.current_release.clone());
+                            }
+                        }
+                        if let Some(reason) = skipped? {
+                            self.skip_log.record("release", self.current_id, &reason)?;
+                        }
+                        let full = self.releases.len() + self.rejected.len()
+                            >= self.db_opts.batch_size
+                            || self
+                                .db_opts
+                                .max_batch_bytes
+                                .is_some_and(|max| self.batch_bytes >= max);
+                        if full {
+                            // write to db every --batch-size records or --max-batch-bytes
+                            self.write_batch()?;
                         }
                         self.pb.inc(1);
                         ParserReadState::Release
                     }
 
                     Event::End(e) if e.local_name() == b"releases" => {
-                        // write to db remainder of releases
-                        write_releases(
-                            self.db_opts,
-                            &self.releases,
-                            &self.release_labels,
-                            &self.release_videos,
-                            &self.tracks,
-                            &self.formats,
-                        )?;
+                        self.finish()?;
                         ParserReadState::Release
                     }
 
@@ -332,12 +1030,34 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                     b"title" => ParserReadState::TrackTitle,
                     b"position" => ParserReadState::TrackPosition,
                     b"duration" => ParserReadState::TrackDuration,
+                    b"artists" => {
+                        // so the track has its sequence for the artists to refer to
+                        self.current_track();
+                        ParserReadState::TrackArtists
+                    }
+                    b"sub_tracks" => {
+                        self.current_track().track_type = String::from("index");
+                        // the sub-tracks follow the index track
+                        self.parent_track_id = Some(self.current_track_id);
+                        self.current_track_id += 1;
+                        ParserReadState::SubTracks
+                    }
                     _ => ParserReadState::Track,
                 },
 
                 Event::End(e) if e.local_name() == b"track" => {
+                    // headings separate sections of a tracklist and have no position
+                    if let Some(track) = self.tracks.get_mut(&self.current_track_id) {
+                        if track.position.is_empty() && track.track_type == "track" {
+                            track.track_type = String::from("heading");
+                        }
+                    }
                     self.current_track_id += 1;
-                    ParserReadState::TrackList
+                    if self.parent_track_id.is_some() {
+                        ParserReadState::SubTracks
+                    } else {
+                        ParserReadState::TrackList
+                    }
                 }
 
                 _ => ParserReadState::Track,
@@ -345,11 +1065,9 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::TrackTitle => match ev {
                 Event::Text(e) => {
-                    let track = self
-                        .tracks
-                        .entry(self.current_track_id)
-                        .or_insert(Track::new(self.current_id));
-                    track.title = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let id = self.current_release.id;
+                    let track = self.current_track();
+                    track.title = lossy_text(&e.unescaped()?, "release", id);
                     ParserReadState::TrackTitle
                 }
 
@@ -360,11 +1078,9 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::TrackPosition => match ev {
                 Event::Text(e) => {
-                    let track = self
-                        .tracks
-                        .entry(self.current_track_id)
-                        .or_insert(Track::new(self.current_id));
-                    track.position = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let id = self.current_release.id;
+                    let track = self.current_track();
+                    track.position = lossy_text(&e.unescaped()?, "release", id);
                     ParserReadState::TrackPosition
                 }
 
@@ -375,11 +1091,10 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::TrackDuration => match ev {
                 Event::Text(e) => {
-                    let track = self
-                        .tracks
-                        .entry(self.current_track_id)
-                        .or_insert(Track::new(self.current_id));
-                    track.duration = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let id = self.current_release.id;
+                    let track = self.current_track();
+                    track.duration = lossy_text(&e.unescaped()?, "release", id);
+                    track.duration_seconds = duration_seconds(&track.duration);
                     ParserReadState::TrackDuration
                 }
 
@@ -388,32 +1103,454 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 _ => ParserReadState::TrackDuration,
             },
 
+            ParserReadState::SubTracks => match ev {
+                Event::Start(e) if e.local_name() == b"track" => ParserReadState::Track,
+
+                Event::End(e) if e.local_name() == b"sub_tracks" => {
+                    self.parent_track_id = None;
+                    ParserReadState::SubTracksEnd
+                }
+
+                _ => ParserReadState::SubTracks,
+            },
+
+            // The rest of the index track, its id was used up before the sub-tracks
+            ParserReadState::SubTracksEnd => match ev {
+                Event::End(e) if e.local_name() == b"track" => ParserReadState::TrackList,
+
+                _ => ParserReadState::SubTracksEnd,
+            },
+
+            // with track_artist skipped, its artists are read past without being kept
+            ParserReadState::TrackArtists => match ev {
+                Event::Start(e)
+                    if e.local_name() == b"artist"
+                        && !self.parser_opts.skips(TRACK_ARTIST.name) =>
+                {
+                    let sequence = self.current_track().sequence;
+                    self.track_artists.insert(
+                        self.current_track_artist_id,
+                        TrackArtist::new(self.current_id, sequence),
+                    );
+                    ParserReadState::TrackArtist
+                }
+
+                Event::End(e) if e.local_name() == b"artists" => ParserReadState::Track,
+
+                _ => ParserReadState::TrackArtists,
+            },
+
+            ParserReadState::TrackArtist => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"id" => ParserReadState::TrackArtistId,
+                    b"name" => ParserReadState::TrackArtistName,
+                    b"anv" => ParserReadState::TrackArtistAnv,
+                    b"join" => ParserReadState::TrackArtistJoin,
+                    b"role" => ParserReadState::TrackArtistRole,
+                    _ => ParserReadState::TrackArtist,
+                },
+
+                Event::End(e) if e.local_name() == b"artist" => {
+                    self.current_track_artist_id += 1;
+                    ParserReadState::TrackArtists
+                }
+
+                _ => ParserReadState::TrackArtist,
+            },
+
+            ParserReadState::TrackArtistId => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.track_artists.get_mut(&self.current_track_artist_id)
+                    {
+                        artist.artist_id = Some(str::parse(str::from_utf8(&e.unescaped()?)?)?);
+                    }
+                    ParserReadState::TrackArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::TrackArtist,
+
+                _ => ParserReadState::TrackArtistId,
+            },
+
+            ParserReadState::TrackArtistName => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.track_artists.get_mut(&self.current_track_artist_id)
+                    {
+                        artist.name =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::TrackArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::TrackArtist,
+
+                _ => ParserReadState::TrackArtistName,
+            },
+
+            ParserReadState::TrackArtistAnv => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.track_artists.get_mut(&self.current_track_artist_id)
+                    {
+                        artist.anv =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::TrackArtistAnv
+                }
+
+                Event::End(e) if e.local_name() == b"anv" => ParserReadState::TrackArtist,
+
+                _ => ParserReadState::TrackArtistAnv,
+            },
+
+            ParserReadState::TrackArtistJoin => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.track_artists.get_mut(&self.current_track_artist_id)
+                    {
+                        artist.join =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::TrackArtistJoin
+                }
+
+                Event::End(e) if e.local_name() == b"join" => ParserReadState::TrackArtist,
+
+                _ => ParserReadState::TrackArtistJoin,
+            },
+
+            ParserReadState::TrackArtistRole => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.track_artists.get_mut(&self.current_track_artist_id)
+                    {
+                        artist.role =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::TrackArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::TrackArtist,
+
+                _ => ParserReadState::TrackArtistRole,
+            },
+
             ParserReadState::Companies => match ev {
+                Event::Start(e) if e.local_name() == b"company" => {
+                    self.companies.insert(
+                        self.current_company_id,
+                        ReleaseCompany::new(self.current_id),
+                    );
+                    ParserReadState::Company
+                }
+
                 Event::End(e) if e.local_name() == b"companies" => ParserReadState::Release,
 
                 _ => ParserReadState::Companies,
             },
 
+            ParserReadState::Company => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"id" => ParserReadState::CompanyId,
+                    b"name" => ParserReadState::CompanyName,
+                    b"entity_type_name" => ParserReadState::CompanyEntityTypeName,
+                    _ => ParserReadState::Company,
+                },
+
+                Event::End(e) if e.local_name() == b"company" => {
+                    self.current_company_id += 1;
+                    ParserReadState::Companies
+                }
+
+                _ => ParserReadState::Company,
+            },
+
+            ParserReadState::CompanyId => match ev {
+                Event::Text(e) => {
+                    if let Some(company) = self.companies.get_mut(&self.current_company_id) {
+                        company.company_id = Some(str::parse(str::from_utf8(&e.unescaped()?)?)?);
+                    }
+                    ParserReadState::CompanyId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::Company,
+
+                _ => ParserReadState::CompanyId,
+            },
+
+            ParserReadState::CompanyName => match ev {
+                Event::Text(e) => {
+                    if let Some(company) = self.companies.get_mut(&self.current_company_id) {
+                        company.name =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::CompanyName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::Company,
+
+                _ => ParserReadState::CompanyName,
+            },
+
+            ParserReadState::CompanyEntityTypeName => match ev {
+                Event::Text(e) => {
+                    if let Some(company) = self.companies.get_mut(&self.current_company_id) {
+                        company.entity_type_name =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::CompanyEntityTypeName
+                }
+
+                Event::End(e) if e.local_name() == b"entity_type_name" => ParserReadState::Company,
+
+                _ => ParserReadState::CompanyEntityTypeName,
+            },
+
             ParserReadState::Identifiers => match ev {
+                Event::Empty(e) if e.local_name() == b"identifier" => {
+                    self.identifiers.insert(
+                        self.current_identifier_id,
+                        Identifier {
+                            release_id: self.current_id,
+                            identifier_type: text_attribute(&e, b"type", self.current_id)?
+                                .unwrap_or_default(),
+                            value: text_attribute(&e, b"value", self.current_id)?
+                                .unwrap_or_default(),
+                            description: text_attribute(&e, b"description", self.current_id)?,
+                            denormalized: None,
+                        },
+                    );
+                    self.current_identifier_id += 1;
+                    ParserReadState::Identifiers
+                }
+
                 Event::End(e) if e.local_name() == b"identifiers" => ParserReadState::Release,
 
                 _ => ParserReadState::Identifiers,
             },
 
             ParserReadState::Artists => match ev {
+                Event::Start(e) if e.local_name() == b"artist" => {
+                    self.release_artists
+                        .insert(self.current_artist_id, ReleaseArtist::new(self.current_id));
+                    ParserReadState::Artist
+                }
+
                 Event::End(e) if e.local_name() == b"artists" => ParserReadState::Release,
 
                 _ => ParserReadState::Artists,
             },
 
+            ParserReadState::Artist => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"id" => ParserReadState::ArtistId,
+                    b"name" => ParserReadState::ArtistName,
+                    b"anv" => ParserReadState::ArtistAnv,
+                    b"join" => ParserReadState::ArtistJoin,
+                    b"role" => ParserReadState::ArtistRole,
+                    _ => ParserReadState::Artist,
+                },
+
+                Event::End(e) if e.local_name() == b"artist" => {
+                    self.current_artist_id += 1;
+                    ParserReadState::Artists
+                }
+
+                _ => ParserReadState::Artist,
+            },
+
+            ParserReadState::ArtistId => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.release_artists.get_mut(&self.current_artist_id) {
+                        artist.artist_id = Some(str::parse(str::from_utf8(&e.unescaped()?)?)?);
+                    }
+                    ParserReadState::ArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::Artist,
+
+                _ => ParserReadState::ArtistId,
+            },
+
+            ParserReadState::ArtistName => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.release_artists.get_mut(&self.current_artist_id) {
+                        artist.name =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::Artist,
+
+                _ => ParserReadState::ArtistName,
+            },
+
+            ParserReadState::ArtistAnv => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.release_artists.get_mut(&self.current_artist_id) {
+                        artist.anv =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ArtistAnv
+                }
+
+                Event::End(e) if e.local_name() == b"anv" => ParserReadState::Artist,
+
+                _ => ParserReadState::ArtistAnv,
+            },
+
+            ParserReadState::ArtistJoin => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.release_artists.get_mut(&self.current_artist_id) {
+                        artist.join =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ArtistJoin
+                }
+
+                Event::End(e) if e.local_name() == b"join" => ParserReadState::Artist,
+
+                _ => ParserReadState::ArtistJoin,
+            },
+
+            ParserReadState::ArtistRole => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.release_artists.get_mut(&self.current_artist_id) {
+                        artist.role =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::Artist,
+
+                _ => ParserReadState::ArtistRole,
+            },
+
             ParserReadState::ExtraArtists => match ev {
+                Event::Start(e) if e.local_name() == b"artist" => {
+                    self.extra_artists.insert(
+                        self.current_extra_artist_id,
+                        ExtraArtist::new(self.current_id),
+                    );
+                    ParserReadState::ExtraArtist
+                }
+
                 Event::End(e) if e.local_name() == b"extraartists" => ParserReadState::Release,
 
                 _ => ParserReadState::ExtraArtists,
             },
 
+            ParserReadState::ExtraArtist => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"id" => ParserReadState::ExtraArtistId,
+                    b"name" => ParserReadState::ExtraArtistName,
+                    b"anv" => ParserReadState::ExtraArtistAnv,
+                    b"role" => ParserReadState::ExtraArtistRole,
+                    _ => ParserReadState::ExtraArtist,
+                },
+
+                Event::End(e) if e.local_name() == b"artist" => {
+                    self.current_extra_artist_id += 1;
+                    ParserReadState::ExtraArtists
+                }
+
+                _ => ParserReadState::ExtraArtist,
+            },
+
+            ParserReadState::ExtraArtistId => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.extra_artists.get_mut(&self.current_extra_artist_id)
+                    {
+                        artist.artist_id = Some(str::parse(str::from_utf8(&e.unescaped()?)?)?);
+                    }
+                    ParserReadState::ExtraArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::ExtraArtist,
+
+                _ => ParserReadState::ExtraArtistId,
+            },
+
+            ParserReadState::ExtraArtistName => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.extra_artists.get_mut(&self.current_extra_artist_id)
+                    {
+                        artist.name =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ExtraArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::ExtraArtist,
+
+                _ => ParserReadState::ExtraArtistName,
+            },
+
+            ParserReadState::ExtraArtistAnv => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.extra_artists.get_mut(&self.current_extra_artist_id)
+                    {
+                        artist.anv =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ExtraArtistAnv
+                }
+
+                Event::End(e) if e.local_name() == b"anv" => ParserReadState::ExtraArtist,
+
+                _ => ParserReadState::ExtraArtistAnv,
+            },
+
+            ParserReadState::ExtraArtistRole => match ev {
+                Event::Text(e) => {
+                    if let Some(artist) = self.extra_artists.get_mut(&self.current_extra_artist_id)
+                    {
+                        artist.role =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::ExtraArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::ExtraArtist,
+
+                _ => ParserReadState::ExtraArtistRole,
+            },
+
+            ParserReadState::Skip => match ev {
+                Event::Start(_) => {
+                    self.skip_depth += 1;
+                    ParserReadState::Skip
+                }
+
+                Event::End(_) => {
+                    self.skip_depth -= 1;
+                    if self.skip_depth == 0 {
+                        ParserReadState::Release
+                    } else {
+                        ParserReadState::Skip
+                    }
+                }
+
+                _ => ParserReadState::Skip,
+            },
+
             // Just eat this
             ParserReadState::Images => match ev {
+                Event::Empty(e) | Event::Start(e) if e.local_name() == b"image" => {
+                    self.images.insert(
+                        self.current_image_id,
+                        ReleaseImage {
+                            release_id: self.current_id,
+                            image_type: attribute(&e, b"type")?.unwrap_or_default(),
+                            width: attribute(&e, b"width")?.and_then(|w| w.parse().ok()),
+                            height: attribute(&e, b"height")?.and_then(|h| h.parse().ok()),
+                            // often blanked out in the public dumps
+                            uri: attribute(&e, b"uri")?.unwrap_or_default(),
+                        },
+                    );
+                    self.current_image_id += 1;
+                    ParserReadState::Images
+                }
+
                 Event::End(e) if e.local_name() == b"images" => ParserReadState::Release,
 
                 _ => ParserReadState::Images,
@@ -421,41 +1558,57 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Formats => match ev {
                 Event::Start(e) if e.local_name() == b"format" => {
-                    let name: String = match e.attributes().find(|a| a.as_ref().unwrap().key == b"name") {
-                        Some(Ok(a)) => str::parse(str::from_utf8(&a.value)?)?,
-                        _ => "".to_string()
-                    };
-                    let qty: String = match e.attributes().find(|a| a.as_ref().unwrap().key == b"qty") {
-                        Some(Ok(a)) => str::parse(str::from_utf8(&a.value)?)?,
-                        _ => "".to_string()
-                    };
-                    let text: String = match e.attributes().find(|a| a.as_ref().unwrap().key == b"text") {
-                        Some(Ok(a)) => str::parse(str::from_utf8(&a.value)?)?,
-                        _ => "".to_string()
-                    };
-
-                    self.formats.insert(self.current_format_id, Format::new(self.current_id, name, qty, text));
+                    let name = text_attribute(&e, b"name", self.current_id)?.unwrap_or_default();
+                    let qty = attribute(&e, b"qty")?.unwrap_or_default();
+                    let text = text_attribute(&e, b"text", self.current_id)?.unwrap_or_default();
+
+                    self.formats.insert(
+                        self.current_format_id,
+                        Format::new(self.current_id, name, qty, text),
+                    );
                     ParserReadState::Format
-                },
+                }
 
                 Event::End(e) if e.local_name() == b"formats" => ParserReadState::Release,
 
-
                 _ => ParserReadState::Formats,
             },
 
             ParserReadState::Format => match ev {
+                Event::Start(e) if e.local_name() == b"description" => {
+                    ParserReadState::FormatDescription
+                }
+
                 Event::End(e) if e.local_name() == b"format" => {
                     self.current_format_id += 1;
                     ParserReadState::Formats
-                },
+                }
 
                 _ => ParserReadState::Format,
             },
 
+            ParserReadState::FormatDescription => match ev {
+                Event::Text(e) => {
+                    let description =
+                        lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    if let Some(format) = self.formats.get_mut(&self.current_format_id) {
+                        if self.parser_opts.format_hints {
+                            format.add_hints(&description);
+                        }
+                        format.descriptions.push(description);
+                    }
+                    ParserReadState::FormatDescription
+                }
+
+                Event::End(e) if e.local_name() == b"description" => ParserReadState::Format,
+
+                _ => ParserReadState::FormatDescription,
+            },
+
             ParserReadState::Title => match ev {
                 Event::Text(e) => {
-                    self.current_release.title = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.title =
+                        lossy_text(&e.unescaped()?, "release", self.current_release.id);
                     ParserReadState::Title
                 }
 
@@ -466,7 +1619,8 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Country => match ev {
                 Event::Text(e) => {
-                    self.current_release.country = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.country =
+                        lossy_text(&e.unescaped()?, "release", self.current_release.id);
                     ParserReadState::Country
                 }
 
@@ -477,7 +1631,10 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Released => match ev {
                 Event::Text(e) => {
-                    self.current_release.released = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.released =
+                        lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    self.current_release.released_year =
+                        released_year(&self.current_release.released);
                     ParserReadState::Released
                 }
 
@@ -488,7 +1645,10 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Notes => match ev {
                 Event::Text(e) => {
-                    self.current_release.notes = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    let notes = lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    let (notes, raw) = self.parser_opts.clean_markup(notes);
+                    self.current_release.notes = notes;
+                    self.current_release.notes_raw = raw;
                     ParserReadState::Notes
                 }
 
@@ -507,9 +1667,11 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Genre => match ev {
                 Event::Text(e) => {
-                    self.current_release
-                        .genres
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_release.genres.push(lossy_text(
+                        &e.unescaped()?,
+                        "release",
+                        self.current_release.id,
+                    ));
                     ParserReadState::Genre
                 }
                 Event::End(e) if e.local_name() == b"genre" => ParserReadState::Genres,
@@ -527,9 +1689,11 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Style => match ev {
                 Event::Text(e) => {
-                    self.current_release
-                        .styles
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                    self.current_release.styles.push(lossy_text(
+                        &e.unescaped()?,
+                        "release",
+                        self.current_release.id,
+                    ));
                     ParserReadState::Style
                 }
 
@@ -540,7 +1704,8 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::MasterId => match ev {
                 Event::Text(e) => {
-                    self.current_release.master_id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.master_id =
+                        optional_number(str::from_utf8(&e.unescaped()?)?)?;
                     ParserReadState::MasterId
                 }
 
@@ -552,7 +1717,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
             ParserReadState::DataQuality => match ev {
                 Event::Text(e) => {
                     self.current_release.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                        lossy_text(&e.unescaped()?, "release", self.current_release.id);
                     ParserReadState::DataQuality
                 }
 
@@ -561,24 +1726,28 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 _ => ParserReadState::DataQuality,
             },
 
-            // TODO Verify this is sufficient
             ParserReadState::Labels => match ev {
                 Event::Empty(e) => {
-                    let label_id = str::parse(str::from_utf8(
-                        &e.attributes().nth(2).unwrap()?.unescaped_value()?,
-                    )?)?;
-                    self.release_labels.entry(label_id).or_insert(ReleaseLabel {
-                        release_id: self.current_release.id,
-                        label: str::parse(str::from_utf8(
-                            &e.attributes().next().unwrap()?.unescaped_value()?,
-                        )?)?,
-                        catno: str::parse(str::from_utf8(
-                            &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                        )?)?,
-                        label_id: str::parse(str::from_utf8(
-                            &e.attributes().nth(2).unwrap()?.unescaped_value()?,
-                        )?)?,
-                    });
+                    let label = text_attribute(&e, b"name", self.current_id)?.unwrap_or_default();
+                    let catno = text_attribute(&e, b"catno", self.current_id)?.unwrap_or_default();
+                    let label_id = str::parse(&required_attribute(&e, b"id", self.current_id)?)?;
+                    if self.parser_opts.primary_label
+                        && self.current_release.primary_label.is_none()
+                    {
+                        self.current_release.primary_label = Some(label.clone());
+                        self.current_release.primary_catno = Some(catno.clone());
+                    }
+                    // one row per label and catno, a release can list a label more than once
+                    self.release_labels.insert(
+                        self.current_label_id,
+                        ReleaseLabel {
+                            release_id: self.current_release.id,
+                            label,
+                            catno,
+                            label_id,
+                        },
+                    );
+                    self.current_label_id += 1;
                     ParserReadState::Labels
                 }
 
@@ -587,31 +1756,496 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 _ => ParserReadState::Labels,
             },
 
-            // TODO Fix this
             ParserReadState::Videos => match ev {
                 Event::Start(e) if e.local_name() == b"video" => {
-                    self.release_videos
-                        .entry(self.current_video_id)
-                        .or_insert(ReleaseVideo {
+                    self.release_videos.insert(
+                        self.current_video_id,
+                        ReleaseVideo {
                             release_id: self.current_release.id,
-                            duration: str::parse(str::from_utf8(
-                                &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                            )?)?,
-                            src: str::parse(str::from_utf8(
-                                &e.attributes().next().unwrap()?.unescaped_value()?,
-                            )?)?,
+                            duration: optional_number(
+                                &attribute(&e, b"duration")?.unwrap_or_default(),
+                            )?,
+                            src: attribute(&e, b"src")?.unwrap_or_default(),
                             title: String::new(),
-                        });
-                    self.current_video_id += 1;
-                    ParserReadState::Videos
+                            embed: attribute(&e, b"embed")?.as_deref() == Some("true"),
+                        },
+                    );
+                    ParserReadState::Video
                 }
 
                 Event::End(e) if e.local_name() == b"videos" => ParserReadState::Release,
 
                 _ => ParserReadState::Videos,
             },
+
+            ParserReadState::Video => match ev {
+                Event::Start(e) if e.local_name() == b"title" => ParserReadState::VideoTitle,
+
+                Event::End(e) if e.local_name() == b"video" => {
+                    self.current_video_id += 1;
+                    ParserReadState::Videos
+                }
+
+                _ => ParserReadState::Video,
+            },
+
+            ParserReadState::VideoTitle => match ev {
+                Event::Text(e) => {
+                    if let Some(video) = self.release_videos.get_mut(&self.current_video_id) {
+                        video.title =
+                            lossy_text(&e.unescaped()?, "release", self.current_release.id);
+                    }
+                    ParserReadState::VideoTitle
+                }
+
+                Event::End(e) if e.local_name() == b"title" => ParserReadState::Video,
+
+                _ => ParserReadState::VideoTitle,
+            },
         };
 
         Ok(())
     }
+
+    fn context(&self) -> String {
+        format!("release {}, field {:?}", self.current_id, self.state)
+    }
+
+    fn current_record(&self) -> (&'static str, i32) {
+        ("release", self.current_id)
+    }
+
+    fn discard_record(&mut self) {
+        self.discard_current_release();
+        self.parent_track_id = None;
+        self.skip_depth = 0;
+        self.state = ParserReadState::Release;
+    }
+
+    fn skip_log(&mut self) -> &mut SkipLog {
+        &mut self.skip_log
+    }
+
+    fn records(&self) -> u64 {
+        self.pb.position()
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // write to db remainder of releases
+        if !self.releases.is_empty() || !self.rejected.is_empty() {
+            self.write_batch()?;
+        }
+        self.writer.finish()?;
+        self.skip_log.log_counts();
+        if let Some(facets) = self.facets.take() {
+            facets.log();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artist::Artist;
+    use crate::db::{Table, ARTIST, LABEL, MASTER, MASTER_ARTIST, RELEASE, RELEASE_REJECTED};
+    use crate::label::Label;
+    use crate::master::{Master, MasterArtist};
+    use quick_xml::Reader;
+    use structopt::StructOpt;
+
+    /// Feed `<release>` elements through the parser without writing them, so the parsed
+    /// rows are still in the parser. The options live for the rest of the test run.
+    fn parse(xml: &str, args: &[&str]) -> ReleasesParser<'static> {
+        parse_bytes(xml.as_bytes(), args)
+    }
+
+    /// Like `parse`, for input that isn't valid UTF-8.
+    fn parse_bytes(xml: &[u8], args: &[&str]) -> ReleasesParser<'static> {
+        let mut argv = vec!["discogs-load", "--dry-run", "--quiet"];
+        argv.extend(args);
+        let opt: &'static crate::Opt = Box::leak(Box::new(crate::Opt::from_iter(argv)));
+        let mut parser = ReleasesParser::new(&opt.dbopts, &opt.parseropts).unwrap();
+        let mut reader = Reader::from_reader(xml);
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event(&mut buf).unwrap() {
+                Event::Eof => break,
+                ev => parser.process(ev).unwrap(),
+            }
+            buf.clear();
+        }
+        parser
+    }
+
+    #[test]
+    fn heading_is_typed_apart_from_its_tracks() {
+        let parser = parse(
+            r#"<release id="1" status="Accepted"><title>Live</title><tracklist>
+                <track><position></position><title>Disc 1</title><duration></duration></track>
+                <track><position>1</position><title>Intro</title><duration>1:05</duration></track>
+                <track><position>2</position><title>Outro</title><duration>2:10</duration></track>
+            </tracklist></release>"#,
+            &[],
+        );
+        let tracks: Vec<(&str, &str)> = parser
+            .tracks
+            .values()
+            .map(|t| (t.title.as_str(), t.track_type.as_str()))
+            .collect();
+        assert_eq!(
+            tracks,
+            [
+                ("Disc 1", "heading"),
+                ("Intro", "track"),
+                ("Outro", "track")
+            ]
+        );
+    }
+
+    const CO_PRESSING: &str = r#"<release id="3" status="Accepted"><title>Loveless</title><labels>
+            <label name="Creation Records" catno="CRELP 060" id="4" />
+            <label name="Creation Records" catno="CRELP 060X" id="4" />
+            <label name="Sire" catno="9 26759-1" id="5" />
+        </labels></release>"#;
+
+    #[test]
+    fn primary_label_is_the_first_label() {
+        let parser = parse(CO_PRESSING, &["--primary-label"]);
+        let release = &parser.releases[&3];
+        assert_eq!(release.primary_label.as_deref(), Some("Creation Records"));
+        assert_eq!(release.primary_catno.as_deref(), Some("CRELP 060"));
+        assert_eq!(parser.release_labels.len(), 3);
+    }
+
+    #[test]
+    fn primary_label_is_left_empty_by_default() {
+        let parser = parse(CO_PRESSING, &[]);
+        let release = &parser.releases[&3];
+        assert_eq!(
+            (&release.primary_label, &release.primary_catno),
+            (&None, &None)
+        );
+    }
+
+    #[test]
+    fn company_without_an_id_has_no_company_id() {
+        let parser = parse(
+            r#"<release id="4" status="Accepted"><title>Pressing</title><companies>
+                <company><name>Unknown Plant</name><entity_type_name>Pressed By</entity_type_name></company>
+                <company><id>3</id><name>Optimum</name><entity_type_name>Pressed By</entity_type_name></company>
+            </companies></release>"#,
+            &[],
+        );
+        let mut ids: Vec<Option<i32>> = parser.companies.values().map(|c| c.company_id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [None, Some(3)]);
+    }
+
+    /// The releases of test_data/main_releases.xml, two of them their master's main release.
+    fn main_releases_fixture() -> &'static str {
+        let dump = include_str!("../test_data/main_releases.xml");
+        // without the closing root tag, which would write the releases out of the parser
+        dump.trim_end().strip_suffix("</releases>").unwrap()
+    }
+
+    #[test]
+    fn main_releases_only_keeps_main_releases() {
+        let parser = parse(main_releases_fixture(), &["--main-releases-only"]);
+        let mut ids: Vec<i32> = parser.releases.keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [10, 11]);
+    }
+
+    #[test]
+    fn every_release_is_kept_by_default() {
+        let parser = parse(main_releases_fixture(), &[]);
+        assert_eq!(parser.releases.len(), 4);
+    }
+
+    #[test]
+    fn invalid_utf8_in_free_text_attributes_is_replaced() {
+        let parser = parse_bytes(
+            b"<release id=\"4\" status=\"Accepted\"><title>Ok</title>\
+              <labels><label name=\"Caf\xe9\" catno=\"C\xe9-1\" id=\"6\" /></labels>\
+              <formats><format name=\"Vinyl\" qty=\"1\" text=\"Pr\xe9ssing\"></format></formats>\
+              <identifiers><identifier type=\"Barcode\" value=\"0\xff1\" description=\"B\xe9\" />\
+              </identifiers></release>",
+            &[],
+        );
+        let label = parser.release_labels.values().next().unwrap();
+        assert_eq!(
+            (label.label.as_str(), label.catno.as_str()),
+            ("Caf\u{fffd}", "C\u{fffd}-1")
+        );
+        assert_eq!(
+            parser.formats.values().next().unwrap().text,
+            "Pr\u{fffd}ssing"
+        );
+        let identifier = parser.identifiers.values().next().unwrap();
+        assert_eq!(identifier.value, "0\u{fffd}1");
+        assert_eq!(identifier.description.as_deref(), Some("B\u{fffd}"));
+    }
+
+    /// Every value of a row has the type of its column, which COPY checks value by value.
+    fn assert_row_types(row: &dyn SqlSerialization, table: &Table) {
+        let columns = table.row_columns(true);
+        let values = row.to_sql();
+        assert_eq!(values.len(), columns.len(), "{} row length", table.name);
+        for (value, (column, col_type)) in values.into_iter().zip(columns) {
+            let mut buf = bytes::BytesMut::new();
+            assert!(
+                value.to_sql_checked(col_type, &mut buf).is_ok(),
+                "{}.{} isn't a {}",
+                table.name,
+                column,
+                col_type
+            );
+        }
+    }
+
+    #[test]
+    fn rows_match_their_column_types() {
+        let children: String = RELEASE_CHILDREN.concat();
+        let xml = format!(
+            r#"<release id="1" status="Accepted">{}</release>"#,
+            children
+        );
+        let parser = parse(
+            &xml,
+            &["--denormalize", "--primary-label", "--strip-markup"],
+        );
+        let rows: Vec<(&dyn SqlSerialization, &Table)> = vec![
+            (&parser.releases[&1], &RELEASE),
+            (&parser.releases[&1], &RELEASE_REJECTED),
+            (
+                parser.release_labels.values().next().unwrap(),
+                &RELEASE_LABEL,
+            ),
+            (
+                parser.release_videos.values().next().unwrap(),
+                &RELEASE_VIDEO,
+            ),
+            (parser.tracks.values().next().unwrap(), &TRACK),
+            (parser.formats.values().next().unwrap(), &FORMAT),
+            (
+                parser.release_artists.values().next().unwrap(),
+                &RELEASE_ARTIST,
+            ),
+            (
+                parser.extra_artists.values().next().unwrap(),
+                &RELEASE_EXTRAARTIST,
+            ),
+            (
+                parser.identifiers.values().next().unwrap(),
+                &RELEASE_IDENTIFIER,
+            ),
+            (parser.companies.values().next().unwrap(), &RELEASE_COMPANY),
+            (parser.images.values().next().unwrap(), &RELEASE_IMAGE),
+            (parser.track_artists.values().next().unwrap(), &TRACK_ARTIST),
+        ];
+        for (row, table) in rows {
+            assert_row_types(row, table);
+        }
+        assert_row_types(&Label::new(), &LABEL);
+        assert_row_types(&Artist::new(), &ARTIST);
+        assert_row_types(&Master::new(), &MASTER);
+        assert_row_types(&MasterArtist::new(), &MASTER_ARTIST);
+    }
+
+    #[test]
+    fn format_hints_come_from_descriptions() {
+        let mut format = Format::new(1, "Vinyl".into(), "1".into(), String::new());
+        for description in ["LP", "Album", "180g", "12\""] {
+            format.add_hints(description);
+        }
+        assert_eq!(
+            (format.weight_grams, format.size_inches),
+            (Some(180), Some(12))
+        );
+
+        let mut plain = Format::new(1, "CD".into(), "1".into(), String::new());
+        for description in ["Album", "Reissue", "Remastered"] {
+            plain.add_hints(description);
+        }
+        assert_eq!((plain.weight_grams, plain.size_inches), (None, None));
+    }
+
+    #[test]
+    fn released_year_is_the_leading_year() {
+        assert_eq!(released_year("1998"), Some(1998));
+        assert_eq!(released_year("1998-05"), Some(1998));
+        assert_eq!(released_year("1998-05-12"), Some(1998));
+        assert_eq!(released_year("0000-00-00"), None);
+        assert_eq!(released_year(""), None);
+        assert_eq!(released_year("May 1998"), None);
+    }
+
+    #[test]
+    fn duration_seconds_reads_minutes_and_hours() {
+        assert_eq!(duration_seconds("4:54"), Some(294));
+        assert_eq!(duration_seconds("1:02:03"), Some(3723));
+        assert_eq!(duration_seconds(""), None);
+        assert_eq!(duration_seconds("4:5x"), None);
+        assert_eq!(duration_seconds("99999999:00"), None);
+        assert_eq!(duration_seconds("4294967295"), None);
+    }
+
+    #[test]
+    fn min_track_count_leaves_out_headings() {
+        let release = r#"<release id="5" status="Accepted"><title>Single</title><tracklist>
+                <track><position></position><title>Side A</title><duration></duration></track>
+                <track><position>A</position><title>Song</title><duration>3:00</duration></track>
+            </tracklist></release>"#;
+        assert!(parse(release, &["--min-track-count", "2"])
+            .releases
+            .is_empty());
+        assert_eq!(
+            parse(release, &["--min-track-count", "1"]).releases.len(),
+            1
+        );
+    }
+
+    // the children of a release in dump order
+    const RELEASE_CHILDREN: &[&str] = &[
+        r#"<images><image type="primary" uri="" width="600" height="600" /></images>"#,
+        r#"<artists><artist><id>1</id><name>Aphex Twin</name><anv></anv><join></join></artist></artists>"#,
+        "<title>Selected Ambient Works 85-92</title>",
+        r#"<labels><label name="Apollo" catno="AMB 3922" id="2" /></labels>"#,
+        r#"<extraartists><artist><id>1</id><name>Richard D. James</name><role>Written-By</role></artist></extraartists>"#,
+        r#"<formats><format name="Vinyl" qty="2" text=""><descriptions><description>LP</description><description>180g</description></descriptions></format></formats>"#,
+        "<genres><genre>Electronic</genre></genres>",
+        "<styles><style>Ambient</style><style>Techno</style></styles>",
+        "<country>Belgium</country>",
+        "<released>1992-11-09</released>",
+        "<notes>Recorded 1985-1992.</notes>",
+        r#"<master_id is_main_release="true">565</master_id>"#,
+        "<data_quality>Correct</data_quality>",
+        r#"<tracklist><track><position>A1</position><title>Xtal</title><duration>4:54</duration>
+            <artists><artist><id>1</id><name>Aphex Twin</name></artist></artists></track>
+            <track><position>A2</position><title>Tha</title><duration>9:01</duration></track></tracklist>"#,
+        r#"<identifiers><identifier type="Matrix / Runout" value="AMB 3922 A" description="Side A" /></identifiers>"#,
+        r#"<videos><video src="https://youtu.be/x" duration="294" embed="true"><title>Xtal</title></video></videos>"#,
+        "<companies><company><id>3</id><name>Optimum</name><entity_type_name>Pressed By</entity_type_name></company></companies>",
+    ];
+
+    /// Parse a release with its children in the given order, followed by a plain release,
+    /// and debug-print everything parsed.
+    fn parse_in_order(order: &[usize]) -> String {
+        let children: String = order.iter().map(|&i| RELEASE_CHILDREN[i]).collect();
+        let parser = parse(
+            &format!(
+                r#"<release id="1" status="Accepted">{}</release>
+                <release id="2" status="Accepted"><title>Next</title></release>"#,
+                children
+            ),
+            &[],
+        );
+        let mut releases: Vec<_> = parser.releases.iter().collect();
+        releases.sort_by_key(|(id, _)| **id);
+        format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            releases,
+            parser.release_labels,
+            parser.release_videos,
+            parser.tracks,
+            parser.formats,
+            parser.release_artists,
+            parser.extra_artists,
+            parser.identifiers,
+            parser.companies,
+            parser.images,
+            parser.track_artists,
+        )
+    }
+
+    #[test]
+    fn child_order_does_not_change_the_parse() {
+        let canonical: Vec<usize> = (0..RELEASE_CHILDREN.len()).collect();
+        let expected = parse_in_order(&canonical);
+        for parsed in [
+            "Xtal",
+            "Apollo",
+            "180g",
+            "Optimum",
+            "Matrix / Runout",
+            "Belgium",
+            "Next",
+        ] {
+            assert!(
+                expected.contains(parsed),
+                "{} missing from {}",
+                parsed,
+                expected
+            );
+        }
+        let reversed: Vec<usize> = canonical.iter().rev().copied().collect();
+        let mut tracklist_first = canonical.clone();
+        tracklist_first.rotate_left(13);
+        // country ahead of formats, title last
+        let mut shuffled = canonical.clone();
+        shuffled.swap(5, 8);
+        shuffled.swap(2, 16);
+        for order in [reversed, tracklist_first, shuffled] {
+            assert_eq!(
+                parse_in_order(&order),
+                expected,
+                "children in order {:?}",
+                order
+            );
+        }
+    }
+
+    const DENORMALIZED_RELEASE: &str = r#"<release id="2" status="Accepted"><title>Bookends</title>
+        <artists>
+            <artist><id>1</id><name>Paul Simon</name><anv>Simon</anv><join>&amp;</join></artist>
+            <artist><id>2</id><name>Art Garfunkel</name><anv>Garfunkel</anv><join></join></artist>
+        </artists>
+        <formats><format name="Vinyl" qty="1" text=""></format></formats>
+        <identifiers><identifier type="Barcode" value="07464" /></identifiers>
+        <tracklist>
+            <track><position>A1</position><title>Bookends Theme</title><duration>0:32</duration></track>
+            <track><position>A2</position><title>Save The Life Of My Child</title><duration>2:49</duration></track>
+        </tracklist></release>"#;
+
+    #[test]
+    fn denormalize_copies_the_release_onto_its_rows() {
+        let parser = parse(DENORMALIZED_RELEASE, &["--denormalize"]);
+        let expected = ("Bookends", "Simon & Garfunkel");
+        let denormalized = |d: &Option<Denormalized>| {
+            let d = d.as_ref().unwrap();
+            (d.release_title.clone(), d.release_artist_display.clone())
+        };
+        assert_eq!(parser.tracks.len(), 2);
+        for track in parser.tracks.values() {
+            let (title, artists) = denormalized(&track.denormalized);
+            assert_eq!((title.as_str(), artists.as_str()), expected);
+            assert_eq!(
+                track.to_sql().len(),
+                TRACK.columns.len() + crate::db::DENORMALIZED.len()
+            );
+        }
+        assert_eq!((parser.formats.len(), parser.identifiers.len()), (1, 1));
+        for format in parser.formats.values() {
+            let (title, artists) = denormalized(&format.denormalized);
+            assert_eq!((title.as_str(), artists.as_str()), expected);
+        }
+        for identifier in parser.identifiers.values() {
+            let (title, artists) = denormalized(&identifier.denormalized);
+            assert_eq!((title.as_str(), artists.as_str()), expected);
+        }
+    }
+
+    #[test]
+    fn rows_are_not_denormalized_by_default() {
+        let parser = parse(DENORMALIZED_RELEASE, &[]);
+        assert!(parser.tracks.values().all(|t| t.denormalized.is_none()));
+        assert!(parser.formats.values().all(|f| f.denormalized.is_none()));
+        assert!(parser
+            .identifiers
+            .values()
+            .all(|i| i.denormalized.is_none()));
+        let track = parser.tracks.values().next().unwrap();
+        assert_eq!(track.to_sql().len(), TRACK.columns.len());
+    }
 }
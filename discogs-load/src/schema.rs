@@ -0,0 +1,66 @@
+use postgres::types::Type;
+use serde_json::{json, Value};
+
+use crate::db::{Table, TABLES};
+
+/// Avro record schemas for every table.
+pub fn avro_schemas(field_map: &[(String, String)], denormalize: bool) -> Vec<Value> {
+    TABLES
+        .iter()
+        .map(|table| {
+            let fields: Vec<Value> = columns(table, field_map, denormalize)
+                .map(|(name, col_type)| {
+                    let avro_type = if *col_type == Type::INT4 {
+                        json!("int")
+                    } else if *col_type == Type::BOOL {
+                        json!("boolean")
+                    } else if *col_type == Type::TEXT_ARRAY {
+                        json!({"type": "array", "items": "string"})
+                    } else {
+                        json!("string")
+                    };
+                    json!({"name": name, "type": ["null", avro_type], "default": null})
+                })
+                .collect();
+            json!({"type": "record", "name": table.name, "fields": fields})
+        })
+        .collect()
+}
+
+/// JSON Schemas for a row of every table.
+pub fn json_schemas(field_map: &[(String, String)], denormalize: bool) -> Vec<Value> {
+    TABLES
+        .iter()
+        .map(|table| {
+            let properties: serde_json::Map<String, Value> = columns(table, field_map, denormalize)
+                .map(|(name, col_type)| {
+                    let property = if *col_type == Type::INT4 {
+                        json!({"type": ["integer", "null"]})
+                    } else if *col_type == Type::BOOL {
+                        json!({"type": ["boolean", "null"]})
+                    } else if *col_type == Type::TEXT_ARRAY {
+                        json!({"type": ["array", "null"], "items": {"type": "string"}})
+                    } else {
+                        json!({"type": ["string", "null"]})
+                    };
+                    (name.to_string(), property)
+                })
+                .collect();
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": table.name,
+                "type": "object",
+                "properties": properties,
+            })
+        })
+        .collect()
+}
+
+fn columns<'a>(
+    table: &'a Table,
+    field_map: &'a [(String, String)],
+    denormalize: bool,
+) -> impl Iterator<Item = (&'a str, &'a Type)> {
+    let types = table.row_columns(denormalize).into_iter().map(|(_, col_type)| col_type);
+    table.column_names(field_map, denormalize).into_iter().zip(types)
+}
@@ -0,0 +1,53 @@
+use log::info;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    mem,
+    path::PathBuf,
+};
+
+use crate::parser::thousands;
+
+/// Appends an `<entity>\t<id>\t<reason>` line per skipped record to the --skip-log file.
+pub struct SkipLog {
+    path: Option<PathBuf>,
+    out: Option<BufWriter<File>>,
+    // skipped records per reason, with or without a --skip-log file
+    counts: BTreeMap<String, usize>,
+}
+
+impl SkipLog {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        SkipLog {
+            path,
+            out: None,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, entity: &str, id: i32, reason: &str) -> io::Result<()> {
+        *self.counts.entry(reason.to_string()).or_insert(0) += 1;
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if self.out.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.out = Some(BufWriter::new(file));
+        }
+        writeln!(self.out.as_mut().unwrap(), "{}\t{}\t{}", entity, id, reason)
+    }
+
+    /// Log the records skipped per reason since the last call.
+    pub fn log_counts(&mut self) {
+        if self.counts.is_empty() {
+            return;
+        }
+        let counts: Vec<String> = mem::take(&mut self.counts)
+            .into_iter()
+            .map(|(reason, records)| format!("{}: {}", reason, thousands(records)))
+            .collect();
+        info!("Records skipped: {}", counts.join(" | "));
+    }
+}
@@ -9,10 +9,21 @@ CREATE INDEX idx_label on label(id);
 CREATE INDEX idx_artist on artist(id);
 
 CREATE INDEX idx_release on release(id);
+CREATE INDEX idx_release_released_year on release(released_year);
 CREATE INDEX idx_release_video on release_video(release_id);
 CREATE INDEX idx_release_label on release_label(release_id);
 CREATE INDEX idx_track on track(release_id);
 CREATE INDEX idx_format on format(release_id);
+CREATE INDEX idx_release_artist_release on release_artist(release_id);
+CREATE INDEX idx_release_artist_artist on release_artist(artist_id);
+CREATE INDEX idx_release_extraartist_release on release_extraartist(release_id);
+CREATE INDEX idx_release_extraartist_artist on release_extraartist(artist_id);
+CREATE INDEX idx_release_identifier on release_identifier(release_id);
+CREATE INDEX idx_release_company_release on release_company(release_id);
+CREATE INDEX idx_release_company_company on release_company(company_id);
+CREATE INDEX idx_release_image on release_image(release_id);
+CREATE INDEX idx_track_artist_release on track_artist(release_id, track_sequence);
+CREATE INDEX idx_track_artist_artist on track_artist(artist_id);
 
 CREATE INDEX idx_master_artist_master on master_artist(master_id);
 CREATE INDEX idx_master_artist_artist on master_artist(artist_id);
\ No newline at end of file
@@ -9,5 +9,8 @@ CREATE TABLE artist (
     name_variations text[],
     urls text[],
     aliases text[],
-    members text[]
+    members text[],
+    groups text[],
+    profile_raw text,
+    source text
 );
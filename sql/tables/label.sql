@@ -8,5 +8,7 @@ CREATE TABLE label (
     parent_label text,
     sublabels text[],
     urls text[],
-    data_quality text
+    data_quality text,
+    profile_raw text,
+    source text
 );
\ No newline at end of file
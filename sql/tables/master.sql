@@ -9,7 +9,11 @@ CREATE TABLE master (
     notes text,
     genres text[],
     styles text[],
-    data_quality text
+    data_quality text,
+    notes_raw text,
+    title_base text,
+    title_disambig int,
+    source text
  );
 
  CREATE TABLE master_artist (
@@ -17,5 +21,6 @@ CREATE TABLE master (
     master_id integer NOT NULL,
     name text,
     anv text,
-    role text
+    role text,
+    source text
 );
\ No newline at end of file
@@ -1,8 +1,15 @@
 DROP TABLE IF EXISTS release CASCADE;
+DROP TABLE IF EXISTS release_rejected CASCADE;
 DROP TABLE IF EXISTS release_label CASCADE;
 DROP TABLE IF EXISTS release_video CASCADE;
 DROP TABLE IF EXISTS track CASCADE;
 DROP TABLE IF EXISTS format CASCADE;
+DROP TABLE IF EXISTS release_artist CASCADE;
+DROP TABLE IF EXISTS release_extraartist CASCADE;
+DROP TABLE IF EXISTS release_identifier CASCADE;
+DROP TABLE IF EXISTS release_company CASCADE;
+DROP TABLE IF EXISTS release_image CASCADE;
+DROP TABLE IF EXISTS track_artist CASCADE;
 
 CREATE TABLE release (
     id int NOT NULL,
@@ -10,19 +17,29 @@ CREATE TABLE release (
     title text,
     country text,
     released text,
+    released_year int,
     notes text,
     genres text[],
     styles text[],
     master_id int,
-    data_quality text
+    data_quality text,
+    notes_raw text,
+    primary_label text,
+    primary_catno text,
+    title_base text,
+    title_disambig int,
+    source text
 );
 
+CREATE TABLE release_rejected (LIKE release);
+
 CREATE TABLE release_label (
     id serial,
     release_id int NOT NULL,
     label_id int,
     label text,
-    catno text
+    catno text,
+    source text
 );
 
 CREATE TABLE release_video (
@@ -30,7 +47,9 @@ CREATE TABLE release_video (
     release_id int NOT NULL,
     duration int,
     src text,
-    title text
+    title text,
+    embed boolean,
+    source text
 );
 
 CREATE TABLE track (
@@ -38,7 +57,12 @@ CREATE TABLE track (
     release_id int NOT NULL,
     title text,
     position text,
-    duration text
+    duration text,
+    duration_seconds int,
+    track_type text,
+    sequence int,
+    parent_sequence int,
+    source text
 );
 
 CREATE TABLE format (
@@ -46,5 +70,70 @@ CREATE TABLE format (
     release_id int NOT NULL,
     name text,
     qty text,
-    text text
-);
\ No newline at end of file
+    text text,
+    descriptions text[],
+    weight_grams int,
+    size_inches int,
+    source text
+);
+
+CREATE TABLE release_artist (
+    id serial,
+    release_id int NOT NULL,
+    artist_id int,
+    name text,
+    anv text,
+    join_string text,
+    role text,
+    source text
+);
+
+CREATE TABLE release_extraartist (
+    id serial,
+    release_id int NOT NULL,
+    artist_id int,
+    name text,
+    anv text,
+    role text,
+    source text
+);
+
+CREATE TABLE release_identifier (
+    id serial,
+    release_id int NOT NULL,
+    type text,
+    value text,
+    description text,
+    source text
+);
+
+CREATE TABLE release_company (
+    id serial,
+    release_id int NOT NULL,
+    company_id int,
+    name text,
+    entity_type_name text,
+    source text
+);
+
+CREATE TABLE release_image (
+    id serial,
+    release_id int NOT NULL,
+    type text,
+    width int,
+    height int,
+    uri text,
+    source text
+);
+
+CREATE TABLE track_artist (
+    id serial,
+    release_id int NOT NULL,
+    track_sequence int,
+    artist_id int,
+    name text,
+    anv text,
+    join_string text,
+    role text,
+    source text
+);